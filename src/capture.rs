@@ -0,0 +1,484 @@
+//! Input capture on TIM1 and TIM2
+//!
+//! The counter runs freely at the tick rate given to the constructor and every channel latches
+//! the counter on the selected edges of its pin. Timestamps are extended to 32 bits by counting
+//! the counter overflows, differences between them are computed with `wrapping_sub`.
+//!
+//! The overflows are only counted when `capture`, `now` or `poll_overflow` are called, which
+//! has to happen at least once per 65536 ticks, e.g. from the update interrupt.
+//!
+//! ``` ignore
+//! let ic = InputCapture::tim2(p.TIM2, 1.mhz(), rcc.clocks);
+//! let pin = cortex_m::interrupt::free(|cs| gpiod.pd4.into_alternate_af2(cs));
+//! let echo = ic.channel1(pin, Config::default().edge(Edge::Both));
+//! let rise = nb::block!(ic.capture(&echo))?;
+//! let fall = nb::block!(ic.capture(&echo))?;
+//! let width_us = fall.wrapping_sub(rise);
+//! ```
+//!
+//! The channels borrow the `InputCapture`, so the timer can't be released while a channel is
+//! in use.
+//!
+//! `PwmInput` measures the frequency and duty cycle of a signal on the channel 1 or 2 pin. The
+//! two channels capture the rising and falling edges while the rising edge resets the counter,
+//! so both values are available without any software timestamping:
+//!
+//! ``` ignore
+//! let mut fan = PwmInput::tim2(p.TIM2, pin, 10.hz(), rcc.clocks);
+//! match fan.read() {
+//!     Ok(m) => { rpm = m.frequency.0 * 30; duty = m.duty_cycle(100); }
+//!     Err(nb::Error::Other(Error::Overflow)) => { /* fan stalled */ }
+//!     Err(nb::Error::WouldBlock) => {}
+//! }
+//! ```
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+
+use cast::u16;
+
+use crate::pac::{RCC, TIM1, TIM2};
+use crate::pwm::{Channel, PwmPin, C1, C2, C3, C4};
+use crate::rcc::Clocks;
+use crate::time::Hertz;
+
+/// Input capture error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// A capture was overwritten before it was read
+    Overcapture,
+    /// The signal period exceeds the counter range or the signal stopped
+    Overflow,
+}
+
+/// Input capture interrupt events
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Counter overflow
+    Overflow,
+    /// Capture on channel 1
+    Capture1,
+    /// Capture on channel 2
+    Capture2,
+    /// Capture on channel 3
+    Capture3,
+    /// Capture on channel 4
+    Capture4,
+}
+
+/// Pin that can be sampled by capture channel `CH` of `TIM`, the same pins as for PWM
+pub trait CapturePin<TIM, CH> {}
+
+impl<TIM, CH, PIN: PwmPin<TIM, CH>> CapturePin<TIM, CH> for PIN {}
+
+/// Edges that trigger a capture
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Both,
+}
+
+/// Number of edges per capture
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Prescaler {
+    Div1 = 0b00,
+    Div2 = 0b01,
+    Div4 = 0b10,
+    Div8 = 0b11,
+}
+
+/// Capture channel configuration
+///
+/// The default captures every rising edge without filter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub edge: Edge,
+    /// Input filter, the ICxF value (0 to 15) from the reference manual. Higher values require
+    /// the input to be stable for more samples before an edge is accepted.
+    pub filter: u8,
+    pub prescaler: Prescaler,
+}
+
+impl Config {
+    pub fn edge(mut self, edge: Edge) -> Self {
+        self.edge = edge;
+        self
+    }
+
+    pub fn filter(mut self, filter: u8) -> Self {
+        self.filter = filter & 0xF;
+        self
+    }
+
+    pub fn prescaler(mut self, prescaler: Prescaler) -> Self {
+        self.prescaler = prescaler;
+        self
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            edge: Edge::Rising,
+            filter: 0,
+            prescaler: Prescaler::Div1,
+        }
+    }
+}
+
+/// Channel whose pin can be measured by `PwmInput`
+pub trait PwmInputChannel: Channel {}
+
+impl PwmInputChannel for C1 {}
+impl PwmInputChannel for C2 {}
+
+/// One period of a measured PWM signal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Measurement {
+    pub frequency: Hertz,
+    /// Length of the high phase in ticks
+    pub high_ticks: u32,
+    /// Length of the period in ticks
+    pub period_ticks: u32,
+}
+
+impl Measurement {
+    /// Returns the duty cycle scaled to `scale`, e.g. in percent for a `scale` of 100
+    pub fn duty_cycle(&self, scale: u16) -> u16 {
+        (self.high_ticks.min(self.period_ticks) * scale as u32 / self.period_ticks) as u16
+    }
+}
+
+/// Frequency and duty cycle measurement of a PWM signal
+pub struct PwmInput<TIM, CH, PIN> {
+    tim: TIM,
+    clocks: Clocks,
+    pin: PIN,
+    _channel: PhantomData<CH>,
+}
+
+/// Free running timer used for input capture
+pub struct InputCapture<TIM> {
+    tim: TIM,
+    clocks: Clocks,
+    overflows: Cell<u32>,
+}
+
+/// Capture channel sampling `PIN`, borrowing the `InputCapture` it belongs to
+pub struct CaptureChannel<'a, TIM, CH, PIN> {
+    pin: PIN,
+    _channel: PhantomData<(&'a (), TIM, CH)>,
+}
+
+macro_rules! capture {
+    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident),)+) => {
+        $(
+            impl InputCapture<$TIM> {
+                /// Configures a TIM peripheral as a free running counter incrementing at
+                /// `tick_rate`
+                ///
+                /// The rate is limited to between the timer clock frequency divided by 65536 and
+                /// the timer clock frequency, `tick_rate` returns the rate actually achieved.
+                pub fn $tim<T>(tim: $TIM, tick_rate: T, clocks: Clocks) -> Self
+                where
+                    T: Into<Hertz>,
+                {
+                    // NOTE(unsafe) This executes only during initialisation
+                    let rcc = unsafe { &(*RCC::ptr()) };
+                    // enable and reset peripheral to a clean slate state
+                    rcc.$apbenr().modify(|_, w| w.$timXen().set_bit());
+                    rcc.$apbrstr().modify(|_, w| w.$timXrst().set_bit());
+                    rcc.$apbrstr().modify(|_, w| w.$timXrst().clear_bit());
+
+                    let pclk = clocks.pclk().0;
+                    let psc = (pclk / tick_rate.into().0.clamp(1, pclk) - 1).min(0xFFFF);
+                    tim.psc().write(|w| unsafe { w.bits(psc) });
+                    tim.arr().write(|w| unsafe { w.bits(0xFFFF) });
+                    // load the prescaler, then drop the update flag this sets
+                    tim.egr().write(|w| w.ug().set_bit());
+                    tim.sr().write(|w| unsafe { w.bits(0) });
+
+                    tim.cr1().modify(|_, w| w.cen().set_bit());
+
+                    InputCapture {
+                        tim,
+                        clocks,
+                        overflows: Cell::new(0),
+                    }
+                }
+
+                /// Returns the rate at which the timestamps increment
+                pub fn tick_rate(&self) -> Hertz {
+                    Hertz(self.clocks.pclk().0 / (self.tim.psc().read().bits() + 1))
+                }
+
+                /// Returns a capture channel 1 sampling `pin`
+                pub fn channel1<PIN: CapturePin<$TIM, C1>>(&self, pin: PIN, config: Config) -> CaptureChannel<'_, $TIM, C1, PIN> {
+                    CaptureChannel::new(pin, config)
+                }
+
+                /// Returns a capture channel 2 sampling `pin`
+                pub fn channel2<PIN: CapturePin<$TIM, C2>>(&self, pin: PIN, config: Config) -> CaptureChannel<'_, $TIM, C2, PIN> {
+                    CaptureChannel::new(pin, config)
+                }
+
+                /// Returns a capture channel 3 sampling `pin`
+                pub fn channel3<PIN: CapturePin<$TIM, C3>>(&self, pin: PIN, config: Config) -> CaptureChannel<'_, $TIM, C3, PIN> {
+                    CaptureChannel::new(pin, config)
+                }
+
+                /// Returns a capture channel 4 sampling `pin`
+                pub fn channel4<PIN: CapturePin<$TIM, C4>>(&self, pin: PIN, config: Config) -> CaptureChannel<'_, $TIM, C4, PIN> {
+                    CaptureChannel::new(pin, config)
+                }
+
+                /// Counts a pending counter overflow
+                pub fn poll_overflow(&self) {
+                    if self.tim.sr().read().uif().bit_is_set() {
+                        // NOTE(unsafe) clears only UIF, a read-modify-write could clear a capture
+                        // flag set in between
+                        self.tim.sr().write(|w| unsafe { w.bits(!1 & 0xFFFF) });
+                        self.overflows.set(self.overflows.get().wrapping_add(1));
+                    }
+                }
+
+                /// Returns the current timestamp
+                pub fn now(&self) -> u32 {
+                    let cnt = self.tim.cnt().read().bits() & 0xFFFF;
+                    self.extend(cnt)
+                }
+
+                /// Returns the timestamp of the last capture on `channel`
+                ///
+                /// Returns `Error::Overcapture` if captures were lost since the last call, the
+                /// next call returns the newest capture.
+                pub fn capture<CH: Channel, PIN>(&self, _channel: &CaptureChannel<'_, $TIM, CH, PIN>) -> nb::Result<u32, Error> {
+                    let n = CH::N as u32;
+                    let sr = self.tim.sr().read().bits();
+                    if sr & (1 << n) == 0 {
+                        return Err(nb::Error::WouldBlock);
+                    }
+
+                    if sr & (1 << (n + 8)) != 0 {
+                        // NOTE(unsafe) the status flags are cleared by writing 0, writing 1
+                        // leaves the other flags untouched
+                        self.tim.sr().write(|w| unsafe { w.bits(!(1 << (n + 8)) & 0xFFFF) });
+                        return Err(nb::Error::Other(Error::Overcapture));
+                    }
+
+                    // reading CCRx clears CCxIF
+                    let ccr = match CH::N {
+                        1 => self.tim.ccr1().read().bits(),
+                        2 => self.tim.ccr2().read().bits(),
+                        3 => self.tim.ccr3().read().bits(),
+                        _ => self.tim.ccr4().read().bits(),
+                    } & 0xFFFF;
+
+                    Ok(self.extend(ccr))
+                }
+
+                /// Extends a counter value sampled before now to 32 bits
+                fn extend(&self, value: u32) -> u32 {
+                    let mut overflows = self.overflows.get();
+                    // An overflow that is still pending happened before `value` was sampled if
+                    // the value is small, the counter can't have wrapped twice in between
+                    if self.tim.sr().read().uif().bit_is_set() && value < 0x8000 {
+                        overflows = overflows.wrapping_add(1);
+                    }
+                    self.poll_overflow();
+                    (overflows << 16) | value
+                }
+
+                /// Starts listening for an `event`
+                pub fn listen(&self, event: Event) {
+                    self.tim.dier().modify(|_, w| match event {
+                        Event::Overflow => w.uie().set_bit(),
+                        Event::Capture1 => w.cc1ie().set_bit(),
+                        Event::Capture2 => w.cc2ie().set_bit(),
+                        Event::Capture3 => w.cc3ie().set_bit(),
+                        Event::Capture4 => w.cc4ie().set_bit(),
+                    });
+                }
+
+                /// Stops listening for an `event`
+                pub fn unlisten(&self, event: Event) {
+                    self.tim.dier().modify(|_, w| match event {
+                        Event::Overflow => w.uie().clear_bit(),
+                        Event::Capture1 => w.cc1ie().clear_bit(),
+                        Event::Capture2 => w.cc2ie().clear_bit(),
+                        Event::Capture3 => w.cc3ie().clear_bit(),
+                        Event::Capture4 => w.cc4ie().clear_bit(),
+                    });
+                }
+
+                /// Stops the counter and releases the TIM peripheral
+                pub fn free(self) -> $TIM {
+                    self.tim.cr1().modify(|_, w| w.cen().clear_bit());
+                    self.tim
+                }
+            }
+
+            impl<CH: Channel, PIN> CaptureChannel<'_, $TIM, CH, PIN> {
+                fn new(pin: PIN, config: Config) -> Self {
+                    let mut channel = CaptureChannel { pin, _channel: PhantomData };
+                    channel.set_config(config);
+                    channel
+                }
+
+                /// Changes the edge, filter and prescaler of the channel
+                pub fn set_config(&mut self, config: Config) {
+                    // CCxS = 01 maps the channel input to its own pin
+                    let ccmr = (1 | ((config.prescaler as u32) << 2) | ((config.filter as u32 & 0xF) << 4))
+                        << (8 * ((CH::N as u32 - 1) % 2));
+                    let ccmr_mask = 0xFF << (8 * ((CH::N as u32 - 1) % 2));
+                    // CCxP and CCxNP select the edge, CCxE enables the capture
+                    let polarity: u32 = match config.edge {
+                        Edge::Rising => 0b0000,
+                        Edge::Falling => 0b0010,
+                        Edge::Both => 0b1010,
+                    };
+                    let offset = 4 * (CH::N as u32 - 1);
+                    let ccer_mask = 0b1111 << offset;
+
+                    // NOTE(unsafe) read-modify-write of the bits owned by this channel inside
+                    // a critical section
+                    cortex_m::interrupt::free(|_| unsafe {
+                        let tim = &*$TIM::ptr();
+                        // CCxS can only be written while the channel is off
+                        tim.ccer().modify(|r, w| w.bits(r.bits() & !ccer_mask));
+                        if CH::N <= 2 {
+                            tim.ccmr1_input().modify(|r, w| w.bits((r.bits() & !ccmr_mask) | ccmr));
+                        } else {
+                            tim.ccmr2_input().modify(|r, w| w.bits((r.bits() & !ccmr_mask) | ccmr));
+                        }
+                        tim.ccer().modify(|r, w| w.bits(r.bits() | ((polarity | 1) << offset)));
+                    });
+                }
+
+                /// Stops capturing and releases the pin
+                pub fn release(self) -> PIN {
+                    let bit = 1 << (4 * (CH::N as u32 - 1));
+                    // NOTE(unsafe) read-modify-write inside a critical section
+                    cortex_m::interrupt::free(|_| unsafe {
+                        (*$TIM::ptr()).ccer().modify(|r, w| w.bits(r.bits() & !bit));
+                    });
+                    self.pin
+                }
+            }
+
+            impl<CH: PwmInputChannel, PIN> PwmInput<$TIM, CH, PIN> {
+                /// Configures a TIM peripheral to measure the signal on `pin`, which must not be
+                /// slower than `lowest`
+                ///
+                /// The prescaler is the smallest one for which a period at `lowest` fits into the
+                /// counter, which gives the finest resolution for faster signals.
+                pub fn $tim<T>(tim: $TIM, pin: PIN, lowest: T, clocks: Clocks) -> Self
+                where
+                    PIN: CapturePin<$TIM, CH>,
+                    T: Into<Hertz>,
+                {
+                    // NOTE(unsafe) This executes only during initialisation
+                    let rcc = unsafe { &(*RCC::ptr()) };
+                    // enable and reset peripheral to a clean slate state
+                    rcc.$apbenr().modify(|_, w| w.$timXen().set_bit());
+                    rcc.$apbrstr().modify(|_, w| w.$timXrst().set_bit());
+                    rcc.$apbrstr().modify(|_, w| w.$timXrst().clear_bit());
+
+                    let period = clocks.pclk().0 / lowest.into().0.max(1);
+                    let psc = u16(period.saturating_sub(1) / 0x1_0000).unwrap();
+                    tim.psc().write(|w| unsafe { w.bits(psc as u32) });
+                    tim.arr().write(|w| unsafe { w.bits(0xFFFF) });
+
+                    // The pin's own channel captures the rising edge and the period, the other
+                    // channel captures the falling edge (CCxS = 10) and the high time
+                    let (ccmr, ccer, ts) = if CH::N == 1 {
+                        (0x0201, 0b0011_0001, 0b101)
+                    } else {
+                        (0x0102, 0b0001_0011, 0b110)
+                    };
+                    tim.ccmr1_input().write(|w| unsafe { w.bits(ccmr) });
+                    tim.ccer().write(|w| unsafe { w.bits(ccer) });
+
+                    // the rising edge resets the counter, only an overflow sets UIF
+                    tim.smcr().write(|w| unsafe { w.ts().bits(ts).sms().bits(0b100) });
+                    tim.cr1().modify(|_, w| w.urs().set_bit());
+                    tim.egr().write(|w| w.ug().set_bit());
+                    tim.sr().write(|w| unsafe { w.bits(0) });
+
+                    tim.cr1().modify(|_, w| w.cen().set_bit());
+
+                    PwmInput {
+                        tim,
+                        clocks,
+                        pin,
+                        _channel: PhantomData,
+                    }
+                }
+
+                /// Returns the rate at which the counter increments
+                pub fn tick_rate(&self) -> Hertz {
+                    Hertz(self.clocks.pclk().0 / (self.tim.psc().read().bits() + 1))
+                }
+
+                /// Changes the input filter, the ICxF value (0 to 15) from the reference manual
+                pub fn set_filter(&mut self, filter: u8) {
+                    let filter = (filter as u32 & 0xF) * 0x1010;
+                    self.tim
+                        .ccmr1_input()
+                        .modify(|r, w| unsafe { w.bits((r.bits() & !0xF0F0) | filter) });
+                }
+
+                /// Returns the last complete period of the signal
+                ///
+                /// Returns `Error::Overflow` if no rising edge came within the counter range,
+                /// because the signal is slower than the lowest frequency or constantly low or
+                /// high.
+                pub fn read(&mut self) -> nb::Result<Measurement, Error> {
+                    let sr = self.tim.sr().read();
+                    if sr.uif().bit_is_set() {
+                        // NOTE(unsafe) clears UIF and both capture flags, writing 1 leaves the
+                        // other flags untouched
+                        self.tim.sr().write(|w| unsafe { w.bits(!0b111 & 0xFFFF) });
+                        return Err(nb::Error::Other(Error::Overflow));
+                    }
+
+                    let (period, high) = if CH::N == 1 {
+                        if sr.cc1if().bit_is_clear() {
+                            return Err(nb::Error::WouldBlock);
+                        }
+                        (self.tim.ccr1().read().bits(), self.tim.ccr2().read().bits())
+                    } else {
+                        if sr.cc2if().bit_is_clear() {
+                            return Err(nb::Error::WouldBlock);
+                        }
+                        (self.tim.ccr2().read().bits(), self.tim.ccr1().read().bits())
+                    };
+
+                    // the counter restarts from 0 on the edge that is captured
+                    let period_ticks = (period & 0xFFFF) + 1;
+                    let high_ticks = (high & 0xFFFF) + 1;
+
+                    Ok(Measurement {
+                        frequency: Hertz(self.tick_rate().0 / period_ticks),
+                        high_ticks,
+                        period_ticks,
+                    })
+                }
+
+                /// Stops the counter and releases the TIM peripheral and the pin
+                pub fn free(self) -> ($TIM, PIN) {
+                    self.tim.cr1().modify(|_, w| w.cen().clear_bit());
+                    self.tim.ccer().reset();
+                    self.tim.smcr().reset();
+                    (self.tim, self.pin)
+                }
+            }
+        )+
+    }
+}
+
+capture! {
+    TIM1: (tim1, tim1en, tim1rst, apbenr2, apbrstr2),
+    TIM2: (tim2, tim2en, tim2rst, apbenr1, apbrstr1),
+}
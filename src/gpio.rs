@@ -14,6 +14,12 @@ pub trait GpioExt {
     fn split(self, rcc: &mut Rcc) -> Self::Parts;
 }
 
+/// Alternate function pin whose output driver can be switched to open drain
+pub trait AlternateOpenDrain {
+    /// Turns the pin output driver into open drain
+    fn into_open_drain(self, cs: &cortex_m::interrupt::CriticalSection) -> Self;
+}
+
 trait GpioRegExt {
     fn is_low(&self, pos: u8) -> bool;
     fn is_set_low(&self, pos: u8) -> bool;
@@ -195,7 +201,7 @@ macro_rules! gpio {
                 use super::{
                     Alternate, Analog, Floating, GpioExt, Input, OpenDrain, Output,
                     PullDown, PullUp, PushPull, AF0, AF1, AF2, AF3, AF4, AF5, AF6, AF7,
-                    Pin, GpioRegExt,
+                    Pin, GpioRegExt, AlternateOpenDrain,
                 };
 
                 /// GPIO parts
@@ -237,7 +243,104 @@ macro_rules! gpio {
                     }
                 }
 
+                /// Saved configuration of the port, see [`park_all_unused`]
+                pub struct Snapshot {
+                    /// MODER / PUPDR fields of the parked pins
+                    mask: u32,
+                    moder: u32,
+                    pupdr: u32,
+                }
+
+                /// Puts every pin of the port whose bit is not set in `keep` into analog mode
+                /// with no pull up / pull down, to minimise leakage in stop mode
+                ///
+                /// Bit `n` of `keep` corresponds to pin `n` of the port. The returned snapshot
+                /// restores the previous configuration of the parked pins after wake-up, the
+                /// kept pins are left as they are.
+                ///
+                /// This does not check which pins are in use, [`Unused`] derives the mask from
+                /// the pins handed to it instead.
+                pub fn park_all_unused(_cs: &CriticalSection, keep: u8) -> Snapshot {
+                    // analog mode is 0b11 in MODER, the same bits are cleared in PUPDR
+                    let mut mask = 0u32;
+                    for i in 0..8 {
+                        if keep & (1 << i) == 0 {
+                            mask |= 0b11 << (2 * i);
+                        }
+                    }
+
+                    unsafe {
+                        let reg = &(*$GPIOX::ptr());
+                        let snapshot = Snapshot {
+                            mask,
+                            moder: reg.moder().read().bits() & mask,
+                            pupdr: reg.pupdr().read().bits() & mask,
+                        };
+
+                        reg.pupdr().modify(|r, w| w.bits(r.bits() & !mask));
+                        reg.moder().modify(|r, w| w.bits(r.bits() | mask));
+
+                        snapshot
+                    }
+                }
+
+                impl Snapshot {
+                    /// Restores the mode and pull up / pull down of the pins parked by
+                    /// [`park_all_unused`]
+                    ///
+                    /// Parking doesn't touch the output type and level, so only the fields of the
+                    /// parked pins in MODER and PUPDR are written back.
+                    pub fn restore(self, _cs: &CriticalSection) {
+                        let mask = self.mask;
+                        unsafe {
+                            let reg = &(*$GPIOX::ptr());
+                            reg.pupdr().modify(|r, w| w.bits((r.bits() & !mask) | self.pupdr));
+                            reg.moder().modify(|r, w| w.bits((r.bits() & !mask) | self.moder));
+                        }
+                    }
+                }
+
+                /// Pin of this port
+                pub trait PortPin {
+                    /// Pin number within the port
+                    const INDEX: u8;
+                }
+
+                /// Unused pins of the port, collected to be parked for stop mode
+                ///
+                /// ``` ignore
+                /// let gpioc = p.GPIOC.split(&mut rcc);
+                /// let led = gpioc.pc5.into_push_pull_output(cs);
+                /// let snapshot = gpioc::Unused::new().pin(gpioc.pc3).pin(gpioc.pc4).park(cs);
+                /// ```
+                #[derive(Default)]
+                pub struct Unused {
+                    mask: u8,
+                }
+
+                impl Unused {
+                    pub fn new() -> Self {
+                        Unused { mask: 0 }
+                    }
+
+                    /// Hands an unused pin over for parking
+                    pub fn pin<P: PortPin>(mut self, _pin: P) -> Self {
+                        self.mask |= 1 << P::INDEX;
+                        self
+                    }
+
+                    /// Puts the collected pins into analog mode with no pull up / pull down,
+                    /// leaving all other pins of the port untouched
+                    pub fn park(self, cs: &CriticalSection) -> Snapshot {
+                        park_all_unused(cs, !self.mask)
+                    }
+                }
+
                 $(
+                    impl<MODE> PortPin for $PXi<MODE> {
+                        const INDEX: u8 = $i;
+                    }
+
                     /// Pin
                     pub struct $PXi<MODE> {
                         _mode: PhantomData<MODE>,
@@ -484,6 +587,12 @@ macro_rules! gpio {
                         }
                     }
 
+                    impl<AF> AlternateOpenDrain for $PXi<Alternate<AF>> {
+                        fn into_open_drain(self, cs: &CriticalSection) -> Self {
+                            self.set_open_drain(cs)
+                        }
+                    }
+
                     impl<MODE> $PXi<Output<MODE>> {
                         /// Erases the pin number from the type
                         ///
@@ -594,3 +703,195 @@ gpio!([
         PD7: (pd7, 7, Input<Floating>),
     ]
     ]);
+
+/// Debounced input event
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebounceEvent {
+    /// The input became active
+    Pressed,
+    /// The input became inactive after being active for the given number of ticks
+    Released(u32),
+    /// The input has been active for the configured long press duration (in ticks)
+    LongPress(u32),
+}
+
+/// Debounced input pin
+///
+/// The wrapper samples the inner pin on every call to `tick`, which should be done at a fixed
+/// rate, e.g. from a `timers::Timer` interrupt or from SysTick. The reported level only changes
+/// once the raw input has been stable for `threshold` consecutive ticks.
+pub struct Debounced<P> {
+    pin: P,
+    active_low: bool,
+    threshold: u8,
+    integrator: u8,
+    stable_high: bool,
+    held: u32,
+    long_press: u32,
+    long_reported: bool,
+}
+
+impl<P: InputPin> Debounced<P> {
+    /// Wraps `pin`, treating a low level as pressed when `active_low` is set
+    ///
+    /// `threshold` is the number of stable samples needed to accept a new level and
+    /// `long_press` the number of ticks after which `DebounceEvent::LongPress` is
+    /// reported (0 disables long press detection).
+    pub fn new(mut pin: P, active_low: bool, threshold: u8, long_press: u32) -> Result<Self, P::Error> {
+        let threshold = threshold.max(1);
+        let stable_high = pin.is_high()?;
+        Ok(Debounced {
+            pin,
+            active_low,
+            threshold,
+            integrator: if stable_high { threshold } else { 0 },
+            stable_high,
+            held: 0,
+            long_press,
+            long_reported: false,
+        })
+    }
+
+    /// Samples the pin, should be called at a fixed rate
+    pub fn tick(&mut self) -> Result<Option<DebounceEvent>, P::Error> {
+        if self.pin.is_high()? {
+            self.integrator = self.integrator.saturating_add(1).min(self.threshold);
+        } else {
+            self.integrator = self.integrator.saturating_sub(1);
+        }
+
+        let was_pressed = self.is_pressed();
+        if self.integrator == self.threshold {
+            self.stable_high = true;
+        } else if self.integrator == 0 {
+            self.stable_high = false;
+        }
+        let pressed = self.is_pressed();
+
+        Ok(match (was_pressed, pressed) {
+            (false, true) => {
+                self.held = 0;
+                self.long_reported = false;
+                Some(DebounceEvent::Pressed)
+            }
+            (true, false) => Some(DebounceEvent::Released(self.held)),
+            (true, true) => {
+                self.held = self.held.saturating_add(1);
+                if self.long_press != 0 && !self.long_reported && self.held >= self.long_press {
+                    self.long_reported = true;
+                    Some(DebounceEvent::LongPress(self.held))
+                } else {
+                    None
+                }
+            }
+            (false, false) => None,
+        })
+    }
+
+    /// Returns `true` if the debounced input is active
+    pub fn is_pressed(&self) -> bool {
+        self.stable_high != self.active_low
+    }
+
+    /// Returns the number of ticks the input has been active, 0 when inactive
+    pub fn held_ticks(&self) -> u32 {
+        if self.is_pressed() {
+            self.held
+        } else {
+            0
+        }
+    }
+
+    /// Releases the inner pin
+    pub fn free(self) -> P {
+        self.pin
+    }
+}
+
+impl<P: InputPin> ErrorType for Debounced<P> {
+    type Error = P::Error;
+}
+
+impl<P: InputPin> InputPin for Debounced<P> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.stable_high)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.stable_high)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Input pin returning a preset level
+    struct MockPin {
+        high: bool,
+    }
+
+    impl ErrorType for MockPin {
+        type Error = Infallible;
+    }
+
+    impl InputPin for MockPin {
+        fn is_high(&mut self) -> Result<bool, Infallible> {
+            Ok(self.high)
+        }
+
+        fn is_low(&mut self) -> Result<bool, Infallible> {
+            Ok(!self.high)
+        }
+    }
+
+    fn ticks(button: &mut Debounced<MockPin>, high: bool, n: u32) -> Option<DebounceEvent> {
+        button.pin.high = high;
+        let mut last = None;
+        for _ in 0..n {
+            if let Some(event) = button.tick().unwrap() {
+                last = Some(event);
+            }
+        }
+        last
+    }
+
+    #[test]
+    fn ignores_bounces_shorter_than_the_threshold() {
+        let mut button = Debounced::new(MockPin { high: false }, false, 3, 0).unwrap();
+        assert_eq!(ticks(&mut button, true, 2), None);
+        assert_eq!(ticks(&mut button, false, 1), None);
+        assert_eq!(ticks(&mut button, true, 1), None);
+        assert_eq!(ticks(&mut button, true, 1), Some(DebounceEvent::Pressed));
+        assert!(button.is_pressed());
+    }
+
+    #[test]
+    fn reports_release_and_long_press() {
+        let mut button = Debounced::new(MockPin { high: true }, true, 2, 10).unwrap();
+        assert!(!button.is_pressed());
+        assert_eq!(ticks(&mut button, false, 2), Some(DebounceEvent::Pressed));
+        assert_eq!(
+            ticks(&mut button, false, 10),
+            Some(DebounceEvent::LongPress(10))
+        );
+        assert_eq!(ticks(&mut button, false, 5), None);
+        assert_eq!(button.held_ticks(), 15);
+        assert_eq!(
+            ticks(&mut button, true, 2),
+            Some(DebounceEvent::Released(16))
+        );
+        assert_eq!(button.held_ticks(), 0);
+    }
+
+    #[test]
+    fn maximum_threshold_does_not_overflow() {
+        let mut button = Debounced::new(MockPin { high: false }, false, 255, 0).unwrap();
+        assert_eq!(ticks(&mut button, true, 254), None);
+        assert_eq!(ticks(&mut button, true, 1), Some(DebounceEvent::Pressed));
+        // held high, the integrator stays at the threshold
+        assert_eq!(ticks(&mut button, true, 1000), None);
+        assert!(button.is_pressed());
+        assert_eq!(button.held_ticks(), 1000);
+    }
+}
@@ -7,7 +7,11 @@ pub mod rcc;
 pub mod gpio;
 pub mod time;
 pub mod delay;
+pub mod power;
 pub mod timers;
+pub mod pwm;
+pub mod capture;
 pub mod serial;
 pub mod watchdog;
 pub mod adc;
+pub mod modbus;
@@ -0,0 +1,639 @@
+//! Modbus RTU slave
+//!
+//! Frames are delimited by a silent interval of 3.5 character times, measured with a timer
+//! that is restarted on every received byte. Requests are decoded by `process_request`, which
+//! does not touch the hardware, and served from a `RegisterMap` implemented by the
+//! application.
+//!
+//! Supported function codes: 3 (read holding registers), 4 (read input registers),
+//! 6 (write single register) and 16 (write multiple registers).
+//!
+//! ``` ignore
+//! let timer = Timer::tim6(p.TIM6, 1.khz(), rcc.clocks);
+//! let mut slave = Slave::new(serial, timer, 0x11, 19_200.bps());
+//! loop {
+//!     match slave.poll(&mut registers) {
+//!         Ok(()) | Err(nb::Error::WouldBlock) => {}
+//!         Err(nb::Error::Other(_)) => { /* serial error while answering */ }
+//!     }
+//! }
+//! ```
+
+use embedded_hal_nb::serial::{Read, Write};
+
+use crate::time::{Bps, Hertz};
+use crate::timers::CountDown;
+
+/// Maximum size of a Modbus RTU frame
+pub const MAX_FRAME: usize = 256;
+
+/// Exception codes returned to the master
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exception {
+    /// The function code is not supported
+    IllegalFunction = 0x01,
+    /// The register address is not valid
+    IllegalDataAddress = 0x02,
+    /// A value in the request is not valid
+    IllegalDataValue = 0x03,
+    /// The request could not be performed
+    ServerDeviceFailure = 0x04,
+}
+
+/// Register map served by the slave
+pub trait RegisterMap {
+    /// Reads a holding register
+    fn read_holding(&mut self, address: u16) -> Result<u16, Exception>;
+
+    /// Reads an input register
+    fn read_input(&mut self, address: u16) -> Result<u16, Exception>;
+
+    /// Writes a holding register
+    fn write_holding(&mut self, address: u16, value: u16) -> Result<(), Exception>;
+}
+
+/// Computes the Modbus CRC (CRC-16/MODBUS), transmitted low byte first
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xA001 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+/// Processes a complete request frame (including address and CRC) for the slave `address`
+///
+/// Returns the length of the response frame written to `response`, or `None` if there is
+/// nothing to answer: the frame is addressed to another slave, is a broadcast or is corrupt.
+pub fn process_request<M>(
+    address: u8,
+    request: &[u8],
+    map: &mut M,
+    response: &mut [u8; MAX_FRAME],
+) -> Option<usize>
+where
+    M: RegisterMap,
+{
+    if request.len() < 4 {
+        return None;
+    }
+    let (frame, crc) = request.split_at(request.len() - 2);
+    if crc16(frame).to_le_bytes() != crc {
+        return None;
+    }
+
+    let broadcast = frame[0] == 0;
+    if frame[0] != address && !broadcast {
+        return None;
+    }
+
+    let function = frame[1];
+    response[0] = address;
+    response[1] = function;
+
+    let len = match execute(function, &frame[2..], map, response) {
+        Ok(len) => len,
+        Err(exception) => {
+            response[1] = function | 0x80;
+            response[2] = exception as u8;
+            3
+        }
+    };
+
+    if broadcast {
+        return None;
+    }
+
+    let crc = crc16(&response[..len]).to_le_bytes();
+    response[len..len + 2].copy_from_slice(&crc);
+    Some(len + 2)
+}
+
+/// Executes a request, writes the response PDU after the address and function code and
+/// returns the response length without CRC
+fn execute<M>(
+    function: u8,
+    data: &[u8],
+    map: &mut M,
+    response: &mut [u8; MAX_FRAME],
+) -> Result<usize, Exception>
+where
+    M: RegisterMap,
+{
+    let word = |i: usize| -> Result<u16, Exception> {
+        match data.get(i..i + 2) {
+            Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+            None => Err(Exception::IllegalDataValue),
+        }
+    };
+
+    match function {
+        0x03 | 0x04 => {
+            let start = word(0)?;
+            let count = word(2)?;
+            if count == 0 || count > 125 {
+                return Err(Exception::IllegalDataValue);
+            }
+            if start.checked_add(count - 1).is_none() {
+                return Err(Exception::IllegalDataAddress);
+            }
+
+            response[2] = (count * 2) as u8;
+            for i in 0..count {
+                let value = if function == 0x03 {
+                    map.read_holding(start + i)?
+                } else {
+                    map.read_input(start + i)?
+                };
+                let offset = 3 + 2 * i as usize;
+                response[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+            }
+            Ok(3 + 2 * count as usize)
+        }
+        0x06 => {
+            let register = word(0)?;
+            let value = word(2)?;
+            map.write_holding(register, value)?;
+
+            response[2..6].copy_from_slice(&data[..4]);
+            Ok(6)
+        }
+        0x10 => {
+            let start = word(0)?;
+            let count = word(2)?;
+            let bytes = *data.get(4).ok_or(Exception::IllegalDataValue)? as usize;
+            if count == 0 || count > 123 || bytes != 2 * count as usize || data.len() != 5 + bytes {
+                return Err(Exception::IllegalDataValue);
+            }
+            if start.checked_add(count - 1).is_none() {
+                return Err(Exception::IllegalDataAddress);
+            }
+
+            for i in 0..count {
+                map.write_holding(start + i, word(5 + 2 * i as usize)?)?;
+            }
+
+            response[2..6].copy_from_slice(&data[..4]);
+            Ok(6)
+        }
+        _ => Err(Exception::IllegalFunction),
+    }
+}
+
+/// Modbus RTU slave
+pub struct Slave<S, T> {
+    serial: S,
+    timer: T,
+    address: u8,
+    gap: Hertz,
+    buf: [u8; MAX_FRAME],
+    len: usize,
+    corrupt: bool,
+}
+
+impl<S, T> Slave<S, T>
+where
+    S: Read<u8> + Write<u8>,
+    T: CountDown<Time = Hertz>,
+{
+    /// Creates a slave answering to `address` on a line running at `baud_rate`
+    pub fn new(serial: S, timer: T, address: u8, baud_rate: Bps) -> Self {
+        // 3.5 characters of 11 bits, fixed to 1.75 ms above 19200 baud as the spec requires
+        let gap = if baud_rate.0 > 19_200 {
+            Hertz(571)
+        } else {
+            Hertz((2 * baud_rate.0 / 77).max(1))
+        };
+
+        Slave {
+            serial,
+            timer,
+            address,
+            gap,
+            buf: [0; MAX_FRAME],
+            len: 0,
+            corrupt: false,
+        }
+    }
+
+    /// Receives the available bytes and answers a request once the frame is complete
+    ///
+    /// Returns `Ok(())` when a frame has been handled, must be called more often than the
+    /// inter-character time.
+    pub fn poll<M>(&mut self, map: &mut M) -> nb::Result<(), S::Error>
+    where
+        M: RegisterMap,
+    {
+        loop {
+            match self.serial.read() {
+                Ok(byte) => {
+                    match self.buf.get_mut(self.len) {
+                        Some(slot) => {
+                            *slot = byte;
+                            self.len += 1;
+                        }
+                        None => self.corrupt = true,
+                    }
+                    self.restart_gap();
+                }
+                Err(nb::Error::Other(_)) => {
+                    self.corrupt = true;
+                    self.restart_gap();
+                }
+                Err(nb::Error::WouldBlock) => break,
+            }
+        }
+
+        if (self.len == 0 && !self.corrupt) || self.timer.wait().is_err() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        let len = core::mem::replace(&mut self.len, 0);
+        if core::mem::replace(&mut self.corrupt, false) {
+            return Ok(());
+        }
+
+        let mut response = [0; MAX_FRAME];
+        if let Some(n) = process_request(self.address, &self.buf[..len], map, &mut response) {
+            for &byte in &response[..n] {
+                nb::block!(self.serial.write(byte))?;
+            }
+            nb::block!(self.serial.flush())?;
+        }
+
+        Ok(())
+    }
+
+    fn restart_gap(&mut self) {
+        self.timer.start(self.gap);
+        // Drop a time out left over from before the restart
+        let _ = self.timer.wait();
+    }
+
+    /// Releases the serial port and the timer
+    pub fn free(self) -> (S, T) {
+        (self.serial, self.timer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+    use embedded_hal_nb::serial::{ErrorKind, ErrorType};
+
+    /// Eight holding registers and eight read only input registers at addresses 0 to 7
+    struct Registers {
+        holding: [u16; 8],
+    }
+
+    impl RegisterMap for Registers {
+        fn read_holding(&mut self, address: u16) -> Result<u16, Exception> {
+            let register = self.holding.get(address as usize);
+            register.copied().ok_or(Exception::IllegalDataAddress)
+        }
+
+        fn read_input(&mut self, address: u16) -> Result<u16, Exception> {
+            if address < 8 {
+                Ok(0x1000 + address)
+            } else {
+                Err(Exception::IllegalDataAddress)
+            }
+        }
+
+        fn write_holding(&mut self, address: u16, value: u16) -> Result<(), Exception> {
+            if value == 0xDEAD {
+                return Err(Exception::ServerDeviceFailure);
+            }
+            let register = self.holding.get_mut(address as usize);
+            *register.ok_or(Exception::IllegalDataAddress)? = value;
+            Ok(())
+        }
+    }
+
+    fn registers() -> Registers {
+        Registers {
+            holding: [
+                0x0100, 0x0101, 0x0102, 0x0103, 0x0104, 0x0105, 0x0106, 0x0107,
+            ],
+        }
+    }
+
+    /// Appends the CRC to `pdu`
+    fn frame(pdu: &[u8]) -> ([u8; MAX_FRAME], usize) {
+        let mut frame = [0; MAX_FRAME];
+        frame[..pdu.len()].copy_from_slice(pdu);
+        let crc = crc16(pdu).to_le_bytes();
+        frame[pdu.len()..pdu.len() + 2].copy_from_slice(&crc);
+        (frame, pdu.len() + 2)
+    }
+
+    /// Processes `pdu` for slave 0x11, returns the response without its (checked) CRC
+    fn request(pdu: &[u8], map: &mut Registers) -> Option<([u8; MAX_FRAME], usize)> {
+        let (request, len) = frame(pdu);
+        let mut response = [0; MAX_FRAME];
+        let n = process_request(0x11, &request[..len], map, &mut response)?;
+        assert_eq!(crc16(&response[..n - 2]).to_le_bytes(), response[n - 2..n]);
+        Some((response, n - 2))
+    }
+
+    fn exception(pdu: &[u8]) -> [u8; 3] {
+        let (response, len) = request(pdu, &mut registers()).unwrap();
+        assert_eq!(len, 3);
+        [response[0], response[1], response[2]]
+    }
+
+    #[test]
+    fn crc_vectors() {
+        assert_eq!(crc16(b"123456789"), 0x4B37);
+        assert_eq!(
+            crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A]).to_le_bytes(),
+            [0xC5, 0xCD]
+        );
+    }
+
+    #[test]
+    fn read_holding_registers() {
+        let (response, len) =
+            request(&[0x11, 0x03, 0x00, 0x02, 0x00, 0x03], &mut registers()).unwrap();
+        assert_eq!(
+            &response[..len],
+            &[0x11, 0x03, 0x06, 0x01, 0x02, 0x01, 0x03, 0x01, 0x04]
+        );
+    }
+
+    #[test]
+    fn read_input_registers() {
+        let (response, len) =
+            request(&[0x11, 0x04, 0x00, 0x07, 0x00, 0x01], &mut registers()).unwrap();
+        assert_eq!(&response[..len], &[0x11, 0x04, 0x02, 0x10, 0x07]);
+    }
+
+    #[test]
+    fn write_single_register() {
+        let mut map = registers();
+        let pdu = [0x11, 0x06, 0x00, 0x05, 0xAB, 0xCD];
+        let (response, len) = request(&pdu, &mut map).unwrap();
+        assert_eq!(&response[..len], &pdu);
+        assert_eq!(map.holding[5], 0xABCD);
+    }
+
+    #[test]
+    fn write_multiple_registers() {
+        let mut map = registers();
+        let pdu = [
+            0x11, 0x10, 0x00, 0x01, 0x00, 0x02, 0x04, 0x12, 0x34, 0x56, 0x78,
+        ];
+        let (response, len) = request(&pdu, &mut map).unwrap();
+        assert_eq!(&response[..len], &[0x11, 0x10, 0x00, 0x01, 0x00, 0x02]);
+        assert_eq!(map.holding[..4], [0x0100, 0x1234, 0x5678, 0x0103]);
+    }
+
+    #[test]
+    fn illegal_function() {
+        assert_eq!(
+            exception(&[0x11, 0x05, 0x00, 0x01, 0xFF, 0x00]),
+            [0x11, 0x85, 0x01]
+        );
+    }
+
+    #[test]
+    fn illegal_data_address() {
+        // rejected by the register map
+        assert_eq!(
+            exception(&[0x11, 0x03, 0x00, 0x07, 0x00, 0x02]),
+            [0x11, 0x83, 0x02]
+        );
+        assert_eq!(
+            exception(&[0x11, 0x06, 0x00, 0x08, 0x00, 0x00]),
+            [0x11, 0x86, 0x02]
+        );
+        // past the end of the address space
+        assert_eq!(
+            exception(&[0x11, 0x04, 0xFF, 0xFF, 0x00, 0x02]),
+            [0x11, 0x84, 0x02]
+        );
+        assert_eq!(
+            exception(&[0x11, 0x10, 0xFF, 0xFF, 0x00, 0x02, 0x04, 0, 0, 0, 0]),
+            [0x11, 0x90, 0x02]
+        );
+    }
+
+    #[test]
+    fn illegal_data_value() {
+        // register counts
+        assert_eq!(
+            exception(&[0x11, 0x03, 0x00, 0x00, 0x00, 0x00]),
+            [0x11, 0x83, 0x03]
+        );
+        assert_eq!(
+            exception(&[0x11, 0x04, 0x00, 0x00, 0x00, 0x7E]),
+            [0x11, 0x84, 0x03]
+        );
+        // truncated request
+        assert_eq!(exception(&[0x11, 0x06, 0x00, 0x01]), [0x11, 0x86, 0x03]);
+        // byte count not matching the register count
+        assert_eq!(
+            exception(&[0x11, 0x10, 0x00, 0x00, 0x00, 0x02, 0x02, 0x12, 0x34]),
+            [0x11, 0x90, 0x03]
+        );
+        // byte count not matching the data
+        assert_eq!(
+            exception(&[0x11, 0x10, 0x00, 0x00, 0x00, 0x01, 0x02, 0x12]),
+            [0x11, 0x90, 0x03]
+        );
+    }
+
+    #[test]
+    fn server_device_failure() {
+        assert_eq!(
+            exception(&[0x11, 0x06, 0x00, 0x00, 0xDE, 0xAD]),
+            [0x11, 0x86, 0x04]
+        );
+    }
+
+    #[test]
+    fn broadcast_is_executed_silently() {
+        let mut map = registers();
+        assert!(request(&[0x00, 0x06, 0x00, 0x03, 0x00, 0x2A], &mut map).is_none());
+        assert_eq!(map.holding[3], 0x002A);
+
+        // exceptions are not answered either
+        assert!(request(&[0x00, 0x05, 0x00, 0x00, 0x00, 0x00], &mut map).is_none());
+    }
+
+    #[test]
+    fn ignores_other_slaves_and_bad_frames() {
+        let mut map = registers();
+        let mut response = [0; MAX_FRAME];
+
+        let (mut request, len) = frame(&[0x12, 0x06, 0x00, 0x00, 0x00, 0x2A]);
+        assert_eq!(
+            process_request(0x11, &request[..len], &mut map, &mut response),
+            None
+        );
+
+        request[0] = 0x11;
+        assert_eq!(
+            process_request(0x11, &request[..len], &mut map, &mut response),
+            None
+        );
+        assert_eq!(
+            process_request(0x11, &request[..3], &mut map, &mut response),
+            None
+        );
+        assert_eq!(map.holding[0], 0x0100);
+    }
+
+    /// Serial port receiving from `rx` and recording the transmitted bytes
+    struct MockSerial {
+        rx: [u8; MAX_FRAME],
+        rx_len: usize,
+        rx_pos: usize,
+        tx: [u8; MAX_FRAME],
+        tx_len: usize,
+        flushed: bool,
+    }
+
+    impl MockSerial {
+        fn new() -> Self {
+            MockSerial {
+                rx: [0; MAX_FRAME],
+                rx_len: 0,
+                rx_pos: 0,
+                tx: [0; MAX_FRAME],
+                tx_len: 0,
+                flushed: false,
+            }
+        }
+
+        fn receive(&mut self, data: &[u8]) {
+            self.rx[self.rx_len..self.rx_len + data.len()].copy_from_slice(data);
+            self.rx_len += data.len();
+        }
+    }
+
+    impl ErrorType for MockSerial {
+        type Error = ErrorKind;
+    }
+
+    impl Read<u8> for MockSerial {
+        fn read(&mut self) -> nb::Result<u8, ErrorKind> {
+            if self.rx_pos == self.rx_len {
+                return Err(nb::Error::WouldBlock);
+            }
+            self.rx_pos += 1;
+            Ok(self.rx[self.rx_pos - 1])
+        }
+    }
+
+    impl Write<u8> for MockSerial {
+        fn write(&mut self, byte: u8) -> nb::Result<(), ErrorKind> {
+            self.tx[self.tx_len] = byte;
+            self.tx_len += 1;
+            self.flushed = false;
+            Ok(())
+        }
+
+        fn flush(&mut self) -> nb::Result<(), ErrorKind> {
+            self.flushed = true;
+            Ok(())
+        }
+    }
+
+    /// Count down expiring when the test says so
+    struct MockTimer {
+        timeout: u32,
+        expired: bool,
+    }
+
+    impl CountDown for MockTimer {
+        type Time = Hertz;
+
+        fn start<T>(&mut self, count: T)
+        where
+            T: Into<Hertz>,
+        {
+            self.timeout = count.into().0;
+            self.expired = false;
+        }
+
+        fn wait(&mut self) -> nb::Result<(), Infallible> {
+            if self.expired {
+                self.expired = false;
+                Ok(())
+            } else {
+                Err(nb::Error::WouldBlock)
+            }
+        }
+    }
+
+    fn slave(baud_rate: u32) -> Slave<MockSerial, MockTimer> {
+        let timer = MockTimer {
+            timeout: 0,
+            expired: false,
+        };
+        Slave::new(MockSerial::new(), timer, 0x11, Bps(baud_rate))
+    }
+
+    #[test]
+    fn poll_answers_after_the_silent_interval() {
+        let mut map = registers();
+        let mut slave = slave(9600);
+        assert_eq!(slave.poll(&mut map), Err(nb::Error::WouldBlock));
+
+        let (request, len) = frame(&[0x11, 0x03, 0x00, 0x00, 0x00, 0x01]);
+        slave.serial.receive(&request[..3]);
+        assert_eq!(slave.poll(&mut map), Err(nb::Error::WouldBlock));
+        // 3.5 characters of 11 bits at 9600 baud
+        assert_eq!(slave.timer.timeout, 249);
+
+        slave.serial.receive(&request[3..len]);
+        assert_eq!(slave.poll(&mut map), Err(nb::Error::WouldBlock));
+        assert_eq!(slave.serial.tx_len, 0);
+
+        slave.timer.expired = true;
+        assert_eq!(slave.poll(&mut map), Ok(()));
+        let (response, len) = frame(&[0x11, 0x03, 0x02, 0x01, 0x00]);
+        assert_eq!(&slave.serial.tx[..slave.serial.tx_len], &response[..len]);
+        assert!(slave.serial.flushed);
+
+        // nothing left to answer
+        slave.timer.expired = true;
+        assert_eq!(slave.poll(&mut map), Err(nb::Error::WouldBlock));
+    }
+
+    #[test]
+    fn poll_fixes_the_silent_interval_above_19200_baud() {
+        let mut slave = slave(115_200);
+        slave.serial.receive(&[0x11]);
+        assert_eq!(slave.poll(&mut registers()), Err(nb::Error::WouldBlock));
+        assert_eq!(slave.timer.timeout, 571);
+    }
+
+    #[test]
+    fn poll_drops_overlong_frames() {
+        let mut map = registers();
+        let mut slave = slave(19_200);
+        slave.serial.receive(&[0x11; MAX_FRAME]);
+        assert_eq!(slave.poll(&mut map), Err(nb::Error::WouldBlock));
+        slave.serial = MockSerial::new();
+        slave.serial.receive(&[0x11]);
+        assert_eq!(slave.poll(&mut map), Err(nb::Error::WouldBlock));
+
+        slave.timer.expired = true;
+        assert_eq!(slave.poll(&mut map), Ok(()));
+        assert_eq!(slave.serial.tx_len, 0);
+
+        // the next frame is received from its start
+        let (request, len) = frame(&[0x11, 0x04, 0x00, 0x00, 0x00, 0x01]);
+        slave.serial.receive(&request[..len]);
+        assert_eq!(slave.poll(&mut map), Err(nb::Error::WouldBlock));
+        slave.timer.expired = true;
+        assert_eq!(slave.poll(&mut map), Ok(()));
+        assert_eq!(slave.serial.tx_len, 7);
+    }
+}
@@ -0,0 +1,55 @@
+//! Low power modes
+//!
+//! Peripherals that can wake the core up from stop mode (see `serial::wakeup`) have to be
+//! armed, and their interrupt enabled in the NVIC, before `stop` is called.
+//!
+//! ``` ignore
+//! rcc.set_uart1_clock(UartClock::Hsi);
+//! serial.enable_wakeup(WakeUpSource::StartBit)?;
+//! serial.listen(Event::WakeUp);
+//! while !serial.ready_for_stop() {}
+//! power::stop(&mut cp.SCB, &mut p.PWR, Regulator::LowPower);
+//! serial.clear_wakeup();
+//! ```
+
+use cortex_m::asm;
+use cortex_m::peripheral::SCB;
+
+use crate::pac::PWR;
+
+/// Voltage regulator mode in stop mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Regulator {
+    /// The main regulator stays on, for a faster wake-up
+    Main,
+    /// The regulator runs in low power mode, for a lower stop mode current
+    LowPower,
+}
+
+/// Enters sleep mode until the next interrupt, peripherals keep running
+pub fn sleep(scb: &mut SCB) {
+    scb.clear_sleepdeep();
+    asm::dsb();
+    asm::wfi();
+}
+
+/// Enters stop mode until the next wake-up interrupt
+///
+/// The core clock is stopped, on wake-up execution resumes here after the interrupt handler.
+/// The PWR clock is enabled by `rcc::CFGR::freeze`.
+pub fn stop(scb: &mut SCB, pwr: &mut PWR, regulator: Regulator) {
+    // Deep sleep enters stop rather than standby mode, clear a stale wake-up flag
+    pwr.cr().modify(|_, w| {
+        w.pdds()
+            .clear_bit()
+            .lpds()
+            .bit(regulator == Regulator::LowPower)
+            .cwuf()
+            .set_bit()
+    });
+
+    scb.set_sleepdeep();
+    asm::dsb();
+    asm::wfi();
+    scb.clear_sleepdeep();
+}
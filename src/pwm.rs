@@ -0,0 +1,573 @@
+//! Pulse width modulation on TIM1 and TIM2
+//!
+//! ``` ignore
+//! let pwm = Pwm::tim2(p.TIM2, 20.khz(), rcc.clocks);
+//! let pin = cortex_m::interrupt::free(|cs| gpiod.pd4.into_alternate_af2(cs));
+//! let mut ch1 = pwm.channel1(pin);
+//! ch1.set_duty_cycle_percent(25).unwrap();
+//! ch1.enable();
+//! ```
+//!
+//! The channels borrow the `Pwm`, so the timer can't be released while an output is in use.
+//! The frequency and the other timer settings can still be changed through the shared borrow.
+//!
+//! `set_alignment` switches to center-aligned PWM. The frequency is still the PWM frequency,
+//! the counter runs up and down once per period.
+//!
+//! TIM1 channels 1 to 3 also drive complementary outputs, with a dead time inserted between
+//! the two edges and an optional break input switching all outputs off:
+//!
+//! ``` ignore
+//! let pwm = Pwm::tim1(p.TIM1, 20.khz(), rcc.clocks);
+//! assert!(pwm.set_dead_time(500));
+//! pwm.enable_break(bkin, BreakPolarity::ActiveLow);
+//! let mut phase = pwm.channel1(high_side).complementary(low_side);
+//! phase.set_duty_cycle_fraction(1, 2).unwrap();
+//! phase.enable();
+//! ```
+
+use core::convert::Infallible;
+use core::marker::PhantomData;
+
+use embedded_hal::pwm::{ErrorType, SetDutyCycle};
+
+use crate::gpio::gpioa::PA3;
+use crate::gpio::gpiob::PB5;
+use crate::gpio::gpioc::{PC3, PC4, PC6, PC7};
+use crate::gpio::gpiod::{PD2, PD3, PD4};
+use crate::gpio::{Alternate, AF2, AF3};
+use crate::pac::{RCC, TIM1, TIM2};
+use crate::rcc::Clocks;
+use crate::time::Hertz;
+
+/// Capture compare channel 1
+pub struct C1;
+/// Capture compare channel 2
+pub struct C2;
+/// Capture compare channel 3
+pub struct C3;
+/// Capture compare channel 4
+pub struct C4;
+
+/// Capture compare channel
+pub trait Channel {
+    /// Channel number, starting at 1
+    const N: u8;
+}
+
+impl Channel for C1 {
+    const N: u8 = 1;
+}
+impl Channel for C2 {
+    const N: u8 = 2;
+}
+impl Channel for C3 {
+    const N: u8 = 3;
+}
+impl Channel for C4 {
+    const N: u8 = 4;
+}
+
+/// Pin that can be driven by capture compare channel `CH` of `TIM`
+pub trait PwmPin<TIM, CH> {}
+
+macro_rules! pwm_pins {
+    ($($TIM:ident: $CH:ident => [$($PIN:ident<$AF:ident>),*],)+) => {
+        $(
+            $(
+                impl PwmPin<$TIM, $CH> for $PIN<Alternate<$AF>> {}
+            )*
+        )+
+    }
+}
+
+pwm_pins! {
+    TIM1: C1 => [PC6<AF2>],
+    TIM1: C2 => [PC7<AF2>],
+    TIM1: C3 => [PC3<AF2>],
+    TIM1: C4 => [PC4<AF2>],
+    TIM2: C1 => [PD4<AF2>],
+    TIM2: C2 => [PD3<AF2>],
+    TIM2: C3 => [PA3<AF2>],
+}
+
+/// Pin that can be driven by the complementary output of channel `CH` of `TIM`
+pub trait PwmPinN<TIM, CH> {}
+
+/// Break input pin of `TIM`
+pub trait BreakPin<TIM> {}
+
+impl PwmPinN<TIM1, C1> for PC3<Alternate<AF3>> {}
+impl PwmPinN<TIM1, C2> for PC4<Alternate<AF3>> {}
+impl PwmPinN<TIM1, C3> for PD2<Alternate<AF3>> {}
+impl BreakPin<TIM1> for PB5<Alternate<AF3>> {}
+
+/// Output compare PWM mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PwmMode {
+    /// Output active while the counter is below the compare value
+    Mode1,
+    /// Output inactive while the counter is below the compare value
+    Mode2,
+}
+
+/// Counter alignment
+///
+/// In the center-aligned modes the counter counts up to the reload value and back down, the
+/// update event occurs at both the peak and the valley and the output is symmetric around the
+/// peak. The modes differ in when the compare interrupt flags are set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alignment {
+    /// Edge-aligned, counting up
+    EdgeUp,
+    /// Edge-aligned, counting down
+    EdgeDown,
+    /// Center-aligned, compare flags set while counting down
+    Center1,
+    /// Center-aligned, compare flags set while counting up
+    Center2,
+    /// Center-aligned, compare flags set while counting up and down
+    Center3,
+}
+
+impl Alignment {
+    /// CMS and DIR bits of CR1
+    fn bits(self) -> (u8, bool) {
+        match self {
+            Alignment::EdgeUp => (0b00, false),
+            Alignment::EdgeDown => (0b00, true),
+            Alignment::Center1 => (0b01, false),
+            Alignment::Center2 => (0b10, false),
+            Alignment::Center3 => (0b11, false),
+        }
+    }
+}
+
+/// Timer configured for PWM generation
+pub struct Pwm<TIM> {
+    tim: TIM,
+    clocks: Clocks,
+}
+
+/// PWM output of one capture compare channel, borrowing the `Pwm` it belongs to
+pub struct PwmChannel<'a, TIM, CH, PIN> {
+    pin: PIN,
+    _channel: PhantomData<(&'a (), TIM, CH)>,
+}
+
+macro_rules! pwm {
+    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apbenr:ident, $apbrstr:ident),)+) => {
+        $(
+            impl Pwm<$TIM> {
+                /// Configures a TIM peripheral for PWM generation at `freq`
+                pub fn $tim<T>(tim: $TIM, freq: T, clocks: Clocks) -> Self
+                where
+                    T: Into<Hertz>,
+                {
+                    // NOTE(unsafe) This executes only during initialisation
+                    let rcc = unsafe { &(*RCC::ptr()) };
+                    // enable and reset peripheral to a clean slate state
+                    rcc.$apbenr().modify(|_, w| w.$timXen().set_bit());
+                    rcc.$apbrstr().modify(|_, w| w.$timXrst().set_bit());
+                    rcc.$apbrstr().modify(|_, w| w.$timXrst().clear_bit());
+
+                    let pwm = Pwm { tim, clocks };
+                    pwm.set_frequency(freq);
+
+                    // auto reload preload, start counter
+                    pwm.tim.cr1().modify(|_, w| w.arpe().set_bit().cen().set_bit());
+
+                    pwm
+                }
+
+                /// Changes the PWM frequency, the duty cycles have to be set again afterwards
+                ///
+                /// `freq` is clamped between 1 Hz and half the timer clock frequency, the
+                /// shortest period that still toggles the outputs.
+                pub fn set_frequency<T>(&self, freq: T)
+                where
+                    T: Into<Hertz>,
+                {
+                    let pclk = self.clocks.pclk().0;
+                    self.set_period(pclk / freq.into().0.clamp(1, (pclk / 2).max(1)));
+                }
+
+                /// Returns the PWM frequency
+                pub fn frequency(&self) -> Hertz {
+                    Hertz(self.clocks.pclk().0 / self.period().max(1))
+                }
+
+                /// Programs a PWM period of `ticks` timer clock cycles
+                fn set_period(&self, ticks: u32) {
+                    // ARR is at least 1, with ARR = 0 the counter doesn't run
+                    let ticks = ticks.max(2);
+                    let (psc, arr) = if self.is_center_aligned() {
+                        // one PWM period counts up to ARR and back down
+                        let half = ticks / 2;
+                        let psc = (half - 1) / 0xFFFF;
+                        (psc, half / (psc + 1))
+                    } else {
+                        // ARR stays below 0xFFFF, so that a 100% duty cycle fits into a `u16`
+                        let psc = (ticks - 1) / 0xFFFF;
+                        (psc, ticks / (psc + 1) - 1)
+                    };
+                    self.tim.psc().write(|w| unsafe { w.bits(psc) });
+                    self.tim.arr().write(|w| unsafe { w.bits(arr) });
+
+                    // load the new prescaler and reload values
+                    self.tim.egr().write(|w| w.ug().set_bit());
+                }
+
+                /// Returns the PWM period in timer clock cycles
+                fn period(&self) -> u32 {
+                    let psc = self.tim.psc().read().bits() + 1;
+                    let arr = self.tim.arr().read().bits();
+                    if self.is_center_aligned() {
+                        psc * 2 * arr
+                    } else {
+                        psc * (arr + 1)
+                    }
+                }
+
+                /// Changes the counter alignment, keeping the PWM frequency
+                ///
+                /// The counter is stopped and restarted from zero, the duty cycles have to be set
+                /// again afterwards.
+                pub fn set_alignment(&self, alignment: Alignment) {
+                    let ticks = self.period();
+                    let (cms, dir) = alignment.bits();
+
+                    // CMS can only be changed while the counter is disabled
+                    self.tim.cr1().modify(|_, w| w.cen().clear_bit());
+                    self.tim.cnt().write(|w| unsafe { w.bits(0) });
+                    self.tim
+                        .cr1()
+                        .modify(|_, w| unsafe { w.cms().bits(cms).dir().bit(dir) });
+
+                    self.set_period(ticks);
+                    self.tim.cr1().modify(|_, w| w.cen().set_bit());
+                }
+
+                /// Returns the counter alignment
+                pub fn alignment(&self) -> Alignment {
+                    let cr1 = self.tim.cr1().read();
+                    match (cr1.cms().bits(), cr1.dir().bit_is_set()) {
+                        (0b00, false) => Alignment::EdgeUp,
+                        (0b00, true) => Alignment::EdgeDown,
+                        (0b01, _) => Alignment::Center1,
+                        (0b10, _) => Alignment::Center2,
+                        _ => Alignment::Center3,
+                    }
+                }
+
+                fn is_center_aligned(&self) -> bool {
+                    self.tim.cr1().read().cms().bits() != 0
+                }
+
+                /// Returns a PWM output on channel 1 driving `pin`
+                pub fn channel1<PIN: PwmPin<$TIM, C1>>(&self, pin: PIN) -> PwmChannel<'_, $TIM, C1, PIN> {
+                    PwmChannel::new(pin)
+                }
+
+                /// Returns a PWM output on channel 2 driving `pin`
+                pub fn channel2<PIN: PwmPin<$TIM, C2>>(&self, pin: PIN) -> PwmChannel<'_, $TIM, C2, PIN> {
+                    PwmChannel::new(pin)
+                }
+
+                /// Returns a PWM output on channel 3 driving `pin`
+                pub fn channel3<PIN: PwmPin<$TIM, C3>>(&self, pin: PIN) -> PwmChannel<'_, $TIM, C3, PIN> {
+                    PwmChannel::new(pin)
+                }
+
+                /// Returns a PWM output on channel 4 driving `pin`
+                pub fn channel4<PIN: PwmPin<$TIM, C4>>(&self, pin: PIN) -> PwmChannel<'_, $TIM, C4, PIN> {
+                    PwmChannel::new(pin)
+                }
+
+                /// Stops the counter and releases the TIM peripheral
+                pub fn free(self) -> $TIM {
+                    self.tim.cr1().modify(|_, w| w.cen().clear_bit());
+                    self.tim
+                }
+            }
+
+            impl<CH: Channel, PIN> PwmChannel<'_, $TIM, CH, PIN> {
+                fn new(pin: PIN) -> Self {
+                    let mut channel = PwmChannel { pin, _channel: PhantomData };
+                    channel.set_mode(PwmMode::Mode1);
+                    channel
+                }
+
+                /// Selects PWM mode 1 or 2, with the compare value preloaded
+                pub fn set_mode(&mut self, mode: PwmMode) {
+                    let ocm: u32 = match mode {
+                        PwmMode::Mode1 => 0b110,
+                        PwmMode::Mode2 => 0b111,
+                    };
+                    // OCxPE and OCxM of channel 1 and 3 are in the low byte of CCMRx
+                    let offset = 8 * ((CH::N as u32 - 1) % 2);
+                    let value = ((ocm << 4) | (1 << 3)) << offset;
+                    let mask = 0b1111_1011 << offset;
+
+                    // NOTE(unsafe) read-modify-write of the bits owned by this channel inside
+                    // a critical section
+                    cortex_m::interrupt::free(|_| unsafe {
+                        let tim = &*$TIM::ptr();
+                        if CH::N <= 2 {
+                            tim.ccmr1_output().modify(|r, w| w.bits((r.bits() & !mask) | value));
+                        } else {
+                            tim.ccmr2_output().modify(|r, w| w.bits((r.bits() & !mask) | value));
+                        }
+                    });
+                }
+
+                /// Enables the output
+                pub fn enable(&mut self) {
+                    let bit = 1 << (4 * (CH::N as u32 - 1));
+                    // NOTE(unsafe) read-modify-write inside a critical section
+                    cortex_m::interrupt::free(|_| unsafe {
+                        let tim = &*$TIM::ptr();
+                        tim.ccer().modify(|r, w| w.bits(r.bits() | bit));
+                        Self::enable_outputs(tim);
+                    });
+                }
+
+                /// Disables the output
+                pub fn disable(&mut self) {
+                    let bit = 1 << (4 * (CH::N as u32 - 1));
+                    // NOTE(unsafe) read-modify-write inside a critical section
+                    cortex_m::interrupt::free(|_| unsafe {
+                        (*$TIM::ptr()).ccer().modify(|r, w| w.bits(r.bits() & !bit));
+                    });
+                }
+
+                /// Releases the pin
+                pub fn release(mut self) -> PIN {
+                    self.disable();
+                    self.pin
+                }
+            }
+
+            impl<CH: Channel, PIN> ErrorType for PwmChannel<'_, $TIM, CH, PIN> {
+                type Error = Infallible;
+            }
+
+            impl<CH: Channel, PIN> SetDutyCycle for PwmChannel<'_, $TIM, CH, PIN> {
+                fn max_duty_cycle(&self) -> u16 {
+                    // NOTE(unsafe) atomic reads with no side effects
+                    unsafe {
+                        let tim = &*$TIM::ptr();
+                        let arr = tim.arr().read().bits() as u16;
+                        // center-aligned outputs are fully on once the compare value reaches ARR
+                        if tim.cr1().read().cms().bits() != 0 {
+                            arr
+                        } else {
+                            arr + 1
+                        }
+                    }
+                }
+
+                fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+                    // NOTE(unsafe) atomic write to a register owned by this channel
+                    unsafe {
+                        let tim = &*$TIM::ptr();
+                        match CH::N {
+                            1 => tim.ccr1().write(|w| w.bits(duty as u32)),
+                            2 => tim.ccr2().write(|w| w.bits(duty as u32)),
+                            3 => tim.ccr3().write(|w| w.bits(duty as u32)),
+                            _ => tim.ccr4().write(|w| w.bits(duty as u32)),
+                        };
+                    }
+                    Ok(())
+                }
+            }
+        )+
+    }
+}
+
+pwm! {
+    TIM1: (tim1, tim1en, tim1rst, apbenr2, apbrstr2),
+    TIM2: (tim2, tim2en, tim2rst, apbenr1, apbrstr1),
+}
+
+impl<CH, PIN> PwmChannel<'_, TIM1, CH, PIN> {
+    /// The outputs of the advanced-control timer are gated by the main output enable, which
+    /// stays off after a break until `Pwm::resume`
+    fn enable_outputs(tim: &crate::pac::tim1::RegisterBlock) {
+        if tim.sr().read().bif().bit_is_clear() {
+            tim.bdtr().modify(|_, w| w.moe().set_bit());
+        }
+    }
+}
+
+impl<CH, PIN> PwmChannel<'_, TIM2, CH, PIN> {
+    fn enable_outputs(_tim: &crate::pac::tim2::RegisterBlock) {}
+}
+
+/// Break input polarity
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakPolarity {
+    /// The break is active on a low level
+    ActiveLow,
+    /// The break is active on a high level
+    ActiveHigh,
+}
+
+/// Encodes a dead time of `ticks` timer clock periods into the BDTR DTG field, `None` if it is
+/// too long
+pub fn dead_time_bits(ticks: u32) -> Option<u8> {
+    match ticks {
+        0..=127 => Some(ticks as u8),
+        128..=254 => Some(0b1000_0000 | ((ticks.div_ceil(2) - 64) as u8)),
+        255..=504 => Some(0b1100_0000 | ((ticks.div_ceil(8) - 32) as u8)),
+        505..=1008 => Some(0b1110_0000 | ((ticks.div_ceil(16) - 32) as u8)),
+        _ => None,
+    }
+}
+
+impl Pwm<TIM1> {
+    /// Inserts a dead time of at least `ns` nanoseconds between a complementary output turning
+    /// off and the other one turning on
+    ///
+    /// Returns `false` and leaves the dead time unchanged if it is longer than the timer
+    /// supports at the current clock.
+    #[must_use]
+    pub fn set_dead_time(&self, ns: u32) -> bool {
+        let ticks = (ns as u64 * self.clocks.pclk().0 as u64).div_ceil(1_000_000_000);
+        match dead_time_bits(ticks.min(u32::MAX as u64) as u32) {
+            Some(dtg) => {
+                self.modify_bdtr(|w| unsafe { w.dtg().bits(dtg) });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Enables the break input, all outputs are switched off while it is active
+    pub fn enable_break<P: BreakPin<TIM1>>(&self, _pin: P, polarity: BreakPolarity) {
+        self.modify_bdtr(|w| {
+            w.bkp()
+                .bit(polarity == BreakPolarity::ActiveHigh)
+                .bke()
+                .set_bit()
+        });
+    }
+
+    /// Disables the break input
+    pub fn disable_break(&self) {
+        self.modify_bdtr(|w| w.bke().clear_bit());
+    }
+
+    /// Selects whether the outputs are enabled again automatically at the next update event
+    /// once the break input is inactive, instead of waiting for `resume`
+    ///
+    /// `emergency_stop` turns the automatic output enable off again.
+    pub fn set_automatic_output_enable(&self, on: bool) {
+        self.modify_bdtr(|w| w.aoe().bit(on));
+    }
+
+    /// Returns true if a break occurred since the last `resume`
+    pub fn is_break(&self) -> bool {
+        self.tim.sr().read().bif().bit_is_set()
+    }
+
+    /// Clears the break flag and enables the outputs again
+    pub fn resume(&self) {
+        cortex_m::interrupt::free(|_| {
+            // the flags are cleared by writing 0, BIF is bit 7
+            self.tim.sr().write(|w| unsafe { w.bits(!(1 << 7) & 0xFFFF) });
+            self.tim.bdtr().modify(|_, w| w.moe().set_bit());
+        });
+    }
+
+    /// Switches all TIM1 outputs off immediately by generating a break event
+    ///
+    /// This does not need the `Pwm` instance, so it can be called from a fault or interrupt
+    /// handler. The automatic output enable is turned off as well, the outputs stay off until
+    /// `resume` is called.
+    pub fn emergency_stop() {
+        // NOTE(unsafe) EGR is a stateless write-only register, all other read-modify-writes
+        // of BDTR run in a critical section and can't write back a stale MOE or AOE
+        cortex_m::interrupt::free(|_| unsafe {
+            let tim = &*TIM1::ptr();
+            tim.egr().write(|w| w.bg().set_bit());
+            tim.bdtr().modify(|_, w| w.aoe().clear_bit().moe().clear_bit());
+        });
+    }
+
+    /// Read-modify-write of BDTR that can't race with `emergency_stop`
+    fn modify_bdtr<F>(&self, f: F)
+    where
+        F: for<'w> FnOnce(&'w mut crate::pac::tim1::bdtr::W) -> &'w mut crate::pac::tim1::bdtr::W,
+    {
+        cortex_m::interrupt::free(|_| self.tim.bdtr().modify(|_, w| f(w)));
+    }
+}
+
+/// PWM output of a TIM1 channel with its complementary output
+pub struct ComplementaryPwmChannel<'a, CH, PIN, NPIN> {
+    channel: PwmChannel<'a, TIM1, CH, PIN>,
+    npin: NPIN,
+}
+
+impl<'a, CH: Channel, PIN> PwmChannel<'a, TIM1, CH, PIN> {
+    /// Adds the complementary output driving `npin`
+    pub fn complementary<NPIN: PwmPinN<TIM1, CH>>(
+        self,
+        npin: NPIN,
+    ) -> ComplementaryPwmChannel<'a, CH, PIN, NPIN> {
+        ComplementaryPwmChannel {
+            channel: self,
+            npin,
+        }
+    }
+}
+
+impl<CH: Channel, PIN, NPIN> ComplementaryPwmChannel<'_, CH, PIN, NPIN> {
+    /// Selects PWM mode 1 or 2
+    pub fn set_mode(&mut self, mode: PwmMode) {
+        self.channel.set_mode(mode);
+    }
+
+    /// Enables both outputs
+    pub fn enable(&mut self) {
+        let bits = 0b101 << (4 * (CH::N as u32 - 1));
+        // NOTE(unsafe) read-modify-write inside a critical section
+        cortex_m::interrupt::free(|_| unsafe {
+            let tim = &*TIM1::ptr();
+            tim.ccer().modify(|r, w| w.bits(r.bits() | bits));
+            PwmChannel::<TIM1, CH, PIN>::enable_outputs(tim);
+        });
+    }
+
+    /// Disables both outputs
+    pub fn disable(&mut self) {
+        let bits = 0b101 << (4 * (CH::N as u32 - 1));
+        // NOTE(unsafe) read-modify-write inside a critical section
+        cortex_m::interrupt::free(|_| unsafe {
+            (*TIM1::ptr())
+                .ccer()
+                .modify(|r, w| w.bits(r.bits() & !bits));
+        });
+    }
+
+    /// Releases the pins
+    pub fn release(mut self) -> (PIN, NPIN) {
+        self.disable();
+        (self.channel.pin, self.npin)
+    }
+}
+
+impl<CH: Channel, PIN, NPIN> ErrorType for ComplementaryPwmChannel<'_, CH, PIN, NPIN> {
+    type Error = Infallible;
+}
+
+impl<CH: Channel, PIN, NPIN> SetDutyCycle for ComplementaryPwmChannel<'_, CH, PIN, NPIN> {
+    fn max_duty_cycle(&self) -> u16 {
+        self.channel.max_duty_cycle()
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+        self.channel.set_duty_cycle(duty)
+    }
+}
@@ -45,6 +45,7 @@ pub struct Rcc {
 /// RCC 
 const HSI: u32 = 48_000_000; // Hz
 const LSI: u32 = 60_000; // Hz
+const LSE: u32 = 32_768; // Hz
 
 #[allow(clippy::upper_case_acronyms)]
 enum SysClkSource {
@@ -222,6 +223,54 @@ impl CFGR {
     }
 }
 
+/// Clock of the UART1 baud rate generator
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UartClock {
+    Pclk = 0b00,
+    Sysclk = 0b01,
+    Lse = 0b10,
+    Hsi = 0b11,
+}
+
+impl Rcc {
+    /// Selects the clock of the UART1 baud rate generator
+    ///
+    /// Only HSI and LSE keep running in stop mode, which is required to wake up on UART
+    /// activity. The baud rate divisor is computed from the selected clock, so the UART has to
+    /// be (re)configured after changing it.
+    pub fn set_uart1_clock(&mut self, source: UartClock) {
+        self.regs
+            .cfgr3()
+            .modify(|_, w| unsafe { w.usart1sw().bits(source as u8) });
+    }
+
+    /// Returns the clock of the UART1 baud rate generator
+    pub fn uart1_clock(&self) -> UartClock {
+        uart1_clock()
+    }
+}
+
+/// Reads the UART1 clock selection
+pub(crate) fn uart1_clock() -> UartClock {
+    // NOTE(unsafe) atomic read with no side effects
+    match unsafe { (*RCC::ptr()).cfgr3().read().usart1sw().bits() } {
+        0b00 => UartClock::Pclk,
+        0b01 => UartClock::Sysclk,
+        0b10 => UartClock::Lse,
+        _ => UartClock::Hsi,
+    }
+}
+
+/// Returns the frequency of the UART1 baud rate generator clock
+pub(crate) fn uart1_kernel_clock(clocks: &Clocks) -> Hertz {
+    match uart1_clock() {
+        UartClock::Pclk => clocks.pclk,
+        UartClock::Sysclk => clocks.sysclk,
+        UartClock::Lse => Hertz(LSE),
+        UartClock::Hsi => Hertz(HSI),
+    }
+}
+
 /// Frozen clock frequencies
 ///
 /// The existence of this value indicates that the clock configuration can no longer be changed
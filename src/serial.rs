@@ -1,21 +1,52 @@
 use crate::pac::{RCC, UART1, UART2};
-use crate::rcc::Clocks;
+use crate::rcc::{self, Clocks};
 use crate::time::Bps;
 
-use core::ptr;
+use core::marker::PhantomData;
+use cortex_m::interrupt;
 use embedded_hal_nb;
+use embedded_hal_nb::serial::{Read, Write};
 use nb;
 
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod autobaud;
+pub mod buffered;
+pub mod framing;
+pub mod halfduplex;
+pub mod idle;
+pub mod lin;
+#[cfg(feature = "log")]
+pub mod logger;
+pub mod multiprocessor;
+pub mod rs485;
+pub mod wakeup;
+
 /// Interrupt event
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Event {
     /// New data has been received
     Rxne,
     /// New data can be sent
     Txe,
+    /// Idle line state detected
+    Idle,
+    /// Transmission of the last frame is complete
+    TransmissionComplete,
+    /// Parity error
+    ParityError,
+    /// Framing, noise or overrun error
+    Error,
+    /// LIN break detected
+    LinBreak,
+    /// Receiver timeout
+    ReceiverTimeout,
+    /// Wake-up from stop mode
+    WakeUp,
 }
 
 /// Serial error
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Error {
     /// Framing error
     Framing,
@@ -25,41 +56,463 @@ pub enum Error {
     Overrun,
     /// Parity check error
     Parity,
+    /// Automatic baud rate detection failed
+    AutoBaudRate,
+    /// The requested baud rate can't be reached within the configured tolerance
+    BaudRate,
+    /// The UART kernel clock does not run in stop mode
+    WakeUpClock,
     #[doc(hidden)]
     _Extensible,
 }
 
+impl embedded_io::Error for Error {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Error::Framing | Error::Noise | Error::Parity => embedded_io::ErrorKind::InvalidData,
+            Error::BaudRate => embedded_io::ErrorKind::InvalidInput,
+            Error::Overrun | Error::AutoBaudRate | Error::WakeUpClock | Error::_Extensible => {
+                embedded_io::ErrorKind::Other
+            }
+        }
+    }
+}
+
+impl embedded_hal_nb::serial::Error for Error {
+    fn kind(&self) -> embedded_hal_nb::serial::ErrorKind {
+        match self {
+            Error::Framing => embedded_hal_nb::serial::ErrorKind::FrameFormat,
+            Error::Noise => embedded_hal_nb::serial::ErrorKind::Noise,
+            Error::Overrun => embedded_hal_nb::serial::ErrorKind::Overrun,
+            Error::Parity => embedded_hal_nb::serial::ErrorKind::Parity,
+            Error::AutoBaudRate | Error::BaudRate | Error::WakeUpClock | Error::_Extensible => {
+                embedded_hal_nb::serial::ErrorKind::Other
+            }
+        }
+    }
+}
+
 pub trait Pins<UART> {}
 
+/// Number of data bits per frame, including the parity bit when enabled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WordLength {
+    /// 8 bits per frame
+    DataBits8,
+    /// 9 bits per frame
+    DataBits9,
+}
+
+/// Parity generation and checking
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parity {
+    /// No parity bit
+    ParityNone,
+    /// Even parity
+    ParityEven,
+    /// Odd parity
+    ParityOdd,
+}
+
+/// Number of stop bits
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopBits {
+    /// 1 stop bit
+    STOP1,
+    /// 0.5 stop bits
+    STOP0P5,
+    /// 2 stop bits
+    STOP2,
+    /// 1.5 stop bits
+    STOP1P5,
+}
+
+impl StopBits {
+    fn bits(self) -> u8 {
+        match self {
+            StopBits::STOP1 => 0b00,
+            StopBits::STOP0P5 => 0b01,
+            StopBits::STOP2 => 0b10,
+            StopBits::STOP1P5 => 0b11,
+        }
+    }
+}
+
+/// Serial frame configuration
+///
+/// The default is 115200 baud 8N1 with a baud rate tolerance of 2%. A plain `Bps` converts
+/// into a 8N1 configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub baudrate: Bps,
+    pub wordlength: WordLength,
+    pub parity: Parity,
+    pub stopbits: StopBits,
+    /// Maximum deviation of the achieved baud rate, in tenths of a percent
+    pub baud_tolerance: u16,
+    /// Swaps the TX and RX pin functions
+    pub swap: bool,
+    /// Inverts the RX pin level (idle low)
+    pub rx_invert: bool,
+    /// Inverts the TX pin level (idle low)
+    pub tx_invert: bool,
+    /// Inverts the data bits (1 is sent as low level)
+    pub data_invert: bool,
+    /// Sends and receives the most significant bit first
+    pub msb_first: bool,
+}
+
+impl Config {
+    pub fn baudrate(mut self, baudrate: Bps) -> Self {
+        self.baudrate = baudrate;
+        self
+    }
+
+    pub fn wordlength_8(mut self) -> Self {
+        self.wordlength = WordLength::DataBits8;
+        self
+    }
+
+    pub fn wordlength_9(mut self) -> Self {
+        self.wordlength = WordLength::DataBits9;
+        self
+    }
+
+    pub fn parity_none(mut self) -> Self {
+        self.parity = Parity::ParityNone;
+        self
+    }
+
+    pub fn parity_even(mut self) -> Self {
+        self.parity = Parity::ParityEven;
+        self
+    }
+
+    pub fn parity_odd(mut self) -> Self {
+        self.parity = Parity::ParityOdd;
+        self
+    }
+
+    pub fn stopbits(mut self, stopbits: StopBits) -> Self {
+        self.stopbits = stopbits;
+        self
+    }
+
+    /// Sets the maximum deviation of the achieved baud rate, in tenths of a percent
+    pub fn baud_tolerance(mut self, tolerance: u16) -> Self {
+        self.baud_tolerance = tolerance;
+        self
+    }
+
+    pub fn swap_pins(mut self, swap: bool) -> Self {
+        self.swap = swap;
+        self
+    }
+
+    pub fn rx_invert(mut self, invert: bool) -> Self {
+        self.rx_invert = invert;
+        self
+    }
+
+    pub fn tx_invert(mut self, invert: bool) -> Self {
+        self.tx_invert = invert;
+        self
+    }
+
+    pub fn data_invert(mut self, invert: bool) -> Self {
+        self.data_invert = invert;
+        self
+    }
+
+    pub fn msb_first(mut self, msb_first: bool) -> Self {
+        self.msb_first = msb_first;
+        self
+    }
+}
+
+/// Baud rate divisor
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Divisor {
+    /// Value for the BRR register
+    pub brr: u32,
+    /// 8x instead of 16x oversampling
+    pub over8: bool,
+    /// Baud rate achieved with this divisor
+    pub baudrate: Bps,
+}
+
+impl Divisor {
+    /// Computes the divisor closest to `baudrate` for a UART clocked at `clock` Hz
+    ///
+    /// 8x oversampling has the same resolution as 16x oversampling and is only used for rates
+    /// above `clock / 16`, up to `clock / 8`. Returns `None` if the deviation of the achieved rate
+    /// exceeds `tolerance` (in tenths of a percent).
+    pub fn new(clock: u32, baudrate: Bps, tolerance: u16) -> Option<Self> {
+        let baud = baudrate.0;
+        if baud == 0 {
+            return None;
+        }
+
+        // clock / baudrate, rounded to the nearest integer
+        let div = (clock + baud / 2) / baud;
+        // With 8x oversampling USARTDIV is 2 * clock / baudrate, but bit 0 is not stored in BRR,
+        // so only even values can be programmed: the nearest one is 2 * `div`
+        let usartdiv = 2 * div;
+
+        let candidates = [
+            (16..=0xFFFF).contains(&div).then(|| Divisor {
+                brr: div,
+                over8: false,
+                baudrate: Bps(clock / div),
+            }),
+            (16..=0xFFFF).contains(&usartdiv).then(|| Divisor {
+                brr: (usartdiv & !0xF) | ((usartdiv & 0xF) >> 1),
+                over8: true,
+                baudrate: Bps((2 * clock as u64 / usartdiv as u64) as u32),
+            }),
+        ];
+
+        candidates
+            .iter()
+            .flatten()
+            .copied()
+            .filter(|d| d.deviation(baudrate) <= tolerance as u32)
+            .min_by_key(|d| d.deviation(baudrate))
+    }
+
+    /// Deviation from `baudrate`, in tenths of a percent
+    fn deviation(&self, baudrate: Bps) -> u32 {
+        let diff = self.baudrate.0.abs_diff(baudrate.0) as u64;
+        (diff * 1000 / baudrate.0 as u64) as u32
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            baudrate: Bps(115_200),
+            wordlength: WordLength::DataBits8,
+            parity: Parity::ParityNone,
+            stopbits: StopBits::STOP1,
+            baud_tolerance: 20,
+            swap: false,
+            rx_invert: false,
+            tx_invert: false,
+            data_invert: false,
+            msb_first: false,
+        }
+    }
+}
+
+impl From<Bps> for Config {
+    fn from(baudrate: Bps) -> Self {
+        Config::default().baudrate(baudrate)
+    }
+}
+
 /// Serial abstraction
 pub struct Serial<UART> {
     uart: UART,
 }
 
+/// Serial receiver
+pub struct Rx<UART> {
+    _uart: PhantomData<UART>,
+}
+
+/// Serial transmitter
+pub struct Tx<UART> {
+    _uart: PhantomData<UART>,
+}
+
 macro_rules! uart {
-    ($($UART:ident: ($uart:ident, $uartXen:ident, $apbenr:ident),)+) => {
+    ($($UART:ident: ($uart:ident, $uartXen:ident, $apbenr:ident, $kernel:path),)+) => {
         $(
             /// UART
             impl Serial<$UART> {
-                pub fn $uart(uart: $UART, baud_rate: Bps, clocks: Clocks) -> Self {
+                pub fn $uart<C>(uart: $UART, config: C, clocks: Clocks) -> Result<Self, Error>
+                where
+                    C: Into<Config>,
+                {
                     // NOTE(unsafe) This executes only during initialisation
                     let rcc = unsafe { &(*RCC::ptr()) };
 
                     /* Enable clock for UART */
                     rcc.$apbenr().modify(|_, w| w.$uartXen().set_bit());
 
-                    // Calculate correct baudrate divisor on the fly
-                    let brr = clocks.pclk().0 / baud_rate.0;
-                    uart.brr().write(|w| unsafe { w.bits(brr) });
+                    let mut serial = Serial { uart };
+                    serial.reconfigure(config, clocks)?;
+
+                    Ok(serial)
+                }
+
+                /// Applies a new frame configuration
+                ///
+                /// The UART is disabled while the registers are updated, any frame in progress
+                /// is lost. Nothing is changed if the baud rate can't be reached within the
+                /// configured tolerance.
+                pub fn reconfigure<C>(&mut self, config: C, clocks: Clocks) -> Result<(), Error>
+                where
+                    C: Into<Config>,
+                {
+                    let config = config.into();
+                    let divisor = Divisor::new($kernel(&clocks).0, config.baudrate, config.baud_tolerance)
+                        .ok_or(Error::BaudRate)?;
+
+                    /* Disable the UART while it is being configured */
+                    self.uart.cr1().reset();
+
+                    self.uart.brr().write(|w| unsafe { w.bits(divisor.brr) });
 
                     /* Reset other registers to disable advanced UART features */
-                    uart.cr2().reset();
-                    uart.cr3().reset();
+                    self.uart.cr2().reset();
+                    self.uart.cr3().reset();
+
+                    self.uart.cr2().modify(|_, w| unsafe {
+                        w.stop()
+                            .bits(config.stopbits.bits())
+                            .swap()
+                            .bit(config.swap)
+                            .rxinv()
+                            .bit(config.rx_invert)
+                            .txinv()
+                            .bit(config.tx_invert)
+                            .datainv()
+                            .bit(config.data_invert)
+                            .msbfirst()
+                            .bit(config.msb_first)
+                    });
+
+                    /* Set the frame format, then enable transmission and receiving */
+                    self.uart.cr1().modify(|_, w| {
+                        w.m()
+                            .bit(config.wordlength == WordLength::DataBits9)
+                            .pce()
+                            .bit(config.parity != Parity::ParityNone)
+                            .ps()
+                            .bit(config.parity == Parity::ParityOdd)
+                            .over8()
+                            .bit(divisor.over8)
+                            .te()
+                            .set_bit()
+                            .re()
+                            .set_bit()
+                            .ue()
+                            .set_bit()
+                    });
+
+                    Ok(())
+                }
+
+                /// Runs `f` with the UART disabled, to write the configuration bits that are
+                /// read-only while UE is set
+                ///
+                /// A frame still being transmitted is completed first, the UART is enabled again
+                /// afterwards.
+                fn with_disabled<F>(&mut self, f: F)
+                where
+                    F: FnOnce(&$UART),
+                {
+                    let cr1 = self.uart.cr1().read();
+                    if cr1.ue().bit_is_set() && cr1.te().bit_is_set() {
+                        while self.uart.isr().read().tc().bit_is_clear() {}
+                    }
+                    self.uart.cr1().modify(|_, w| w.ue().clear_bit());
+                    f(&self.uart);
+                    self.uart.cr1().modify(|_, w| w.ue().set_bit());
+                }
+
+                /// Returns the baud rate actually achieved by the programmed divisor
+                pub fn baud_rate(&self, clocks: Clocks) -> Bps {
+                    let brr = self.uart.brr().read().bits();
+                    if self.uart.cr1().read().over8().bit_is_set() {
+                        let usartdiv = (brr & !0xF) | ((brr & 0x7) << 1);
+                        Bps((2 * $kernel(&clocks).0 as u64 / usartdiv.max(1) as u64) as u32)
+                    } else {
+                        Bps($kernel(&clocks).0 / brr.max(1))
+                    }
+                }
+
+                /// Starts listening for an interrupt event
+                pub fn listen(&mut self, event: Event) {
+                    match event {
+                        Event::Rxne => self.uart.cr1().modify(|_, w| w.rxneie().set_bit()),
+                        Event::Txe => self.uart.cr1().modify(|_, w| w.txeie().set_bit()),
+                        Event::Idle => self.uart.cr1().modify(|_, w| w.idleie().set_bit()),
+                        Event::TransmissionComplete => self.uart.cr1().modify(|_, w| w.tcie().set_bit()),
+                        Event::ParityError => self.uart.cr1().modify(|_, w| w.peie().set_bit()),
+                        Event::Error => self.uart.cr3().modify(|_, w| w.eie().set_bit()),
+                        Event::LinBreak => self.uart.cr2().modify(|_, w| w.lbdie().set_bit()),
+                        Event::ReceiverTimeout => self.uart.cr1().modify(|_, w| w.rtoie().set_bit()),
+                        Event::WakeUp => self.uart.cr3().modify(|_, w| w.wufie().set_bit()),
+                    };
+                }
+
+                /// Stops listening for an interrupt event
+                pub fn unlisten(&mut self, event: Event) {
+                    match event {
+                        Event::Rxne => self.uart.cr1().modify(|_, w| w.rxneie().clear_bit()),
+                        Event::Txe => self.uart.cr1().modify(|_, w| w.txeie().clear_bit()),
+                        Event::Idle => self.uart.cr1().modify(|_, w| w.idleie().clear_bit()),
+                        Event::TransmissionComplete => self.uart.cr1().modify(|_, w| w.tcie().clear_bit()),
+                        Event::ParityError => self.uart.cr1().modify(|_, w| w.peie().clear_bit()),
+                        Event::Error => self.uart.cr3().modify(|_, w| w.eie().clear_bit()),
+                        Event::LinBreak => self.uart.cr2().modify(|_, w| w.lbdie().clear_bit()),
+                        Event::ReceiverTimeout => self.uart.cr1().modify(|_, w| w.rtoie().clear_bit()),
+                        Event::WakeUp => self.uart.cr3().modify(|_, w| w.wufie().clear_bit()),
+                    };
+                }
+
+                /// Returns true if the line idle status is set
+                pub fn is_idle(&self) -> bool {
+                    self.uart.isr().read().idle().bit_is_set()
+                }
+
+                /// Returns true if the transmission of the last frame is complete
+                pub fn is_tx_complete(&self) -> bool {
+                    self.uart.isr().read().tc().bit_is_set()
+                }
+
+                /// Returns true if the tx register is empty (and can accept data)
+                pub fn is_txe(&self) -> bool {
+                    self.uart.isr().read().txe().bit_is_set()
+                }
+
+                /// Returns true if the rx register is not empty (and can be read)
+                pub fn is_rx_not_empty(&self) -> bool {
+                    self.uart.isr().read().rxne().bit_is_set()
+                }
+
+                /// Clears the idle line detected flag
+                pub fn clear_idle_interrupt(&self) {
+                    self.uart.icr().write(|w| w.idlecf().set_bit());
+                }
+
+                /// Clears the transmission complete flag
+                pub fn clear_tx_complete(&self) {
+                    self.uart.icr().write(|w| w.tccf().set_bit());
+                }
+
+                /// Clears the parity, framing, noise and overrun error flags
+                pub fn clear_errors(&self) {
+                    self.uart.icr().write(|w| {
+                        w.pecf().set_bit().fecf().set_bit().ncf().set_bit().orecf().set_bit()
+                    });
+                }
 
-                    /* Enable transmission and receiving */
-                    uart.cr1().modify(|_, w| unsafe { w.bits(0xD) });
+                /// Splits the `Serial` abstraction into a transmitter and a receiver half
+                pub fn split(self) -> (Tx<$UART>, Rx<$UART>) {
+                    (
+                        Tx { _uart: PhantomData },
+                        Rx { _uart: PhantomData },
+                    )
+                }
 
-                    Serial { uart }
+                /// Joins the transmitter and receiver halves back into a `Serial`
+                pub fn join(tx: Tx<$UART>, rx: Rx<$UART>) -> Self {
+                    let _ = (tx, rx);
+                    // NOTE(unsafe) both halves are consumed, so this is the only owner of the UART
+                    Serial { uart: unsafe { $UART::steal() } }
                 }
 
                 pub fn release(self) -> $UART {
@@ -67,34 +520,93 @@ macro_rules! uart {
                 }
             }
 
-			impl embedded_hal_nb::serial::ErrorType for Serial<$UART> {
-			    type Error = embedded_hal_nb::serial::ErrorKind;
-			}
+            impl Rx<$UART> {
+                /// Starts listening for the RXNE interrupt event
+                pub fn listen(&mut self) {
+                    // NOTE(unsafe) the read-modify-write of CR1 is guarded against the other half
+                    interrupt::free(|_| unsafe { (*$UART::ptr()).cr1().modify(|_, w| w.rxneie().set_bit()) });
+                }
 
-            impl embedded_hal_nb::serial::Read<u8> for Serial<$UART> {
-                fn read(&mut self) -> nb::Result<u8, Self::Error> {
+                /// Stops listening for the RXNE interrupt event
+                pub fn unlisten(&mut self) {
+                    // NOTE(unsafe) the read-modify-write of CR1 is guarded against the other half
+                    interrupt::free(|_| unsafe { (*$UART::ptr()).cr1().modify(|_, w| w.rxneie().clear_bit()) });
+                }
+            }
+
+            impl Tx<$UART> {
+                /// Starts listening for the TXE interrupt event
+                pub fn listen(&mut self) {
+                    // NOTE(unsafe) the read-modify-write of CR1 is guarded against the other half
+                    interrupt::free(|_| unsafe { (*$UART::ptr()).cr1().modify(|_, w| w.txeie().set_bit()) });
+                }
+
+                /// Stops listening for the TXE interrupt event
+                pub fn unlisten(&mut self) {
+                    // NOTE(unsafe) the read-modify-write of CR1 is guarded against the other half
+                    interrupt::free(|_| unsafe { (*$UART::ptr()).cr1().modify(|_, w| w.txeie().clear_bit()) });
+                }
+            }
+
+            impl embedded_hal_nb::serial::ErrorType for Serial<$UART> {
+                type Error = Error;
+            }
+
+            impl embedded_hal_nb::serial::ErrorType for Rx<$UART> {
+                type Error = Error;
+            }
+
+            impl embedded_hal_nb::serial::ErrorType for Tx<$UART> {
+                type Error = Error;
+            }
+
+            impl Rx<$UART> {
+                /// Reads a 8 or 9 bit word
+                fn read_word(&mut self) -> nb::Result<u16, Error> {
                     // NOTE(unsafe) atomic read with no side effects
                     let isr = unsafe { (*$UART::ptr()).isr().read() };
 
+                    // NOTE(unsafe) write to stateless register, the received data register is
+                    // left untouched so the byte that came with the error is returned by the
+                    // next `read`
+                    let icr = unsafe { (*$UART::ptr()).icr() };
+
                     Err(if isr.pe().bit_is_set() {
-                        nb::Error::Other(Self::Error::Parity)
+                        icr.write(|w| w.pecf().set_bit());
+                        nb::Error::Other(Error::Parity)
                     } else if isr.fe().bit_is_set() {
-                        nb::Error::Other(Self::Error::FrameFormat)
+                        icr.write(|w| w.fecf().set_bit());
+                        nb::Error::Other(Error::Framing)
                     } else if isr.nf().bit_is_set() {
-                        nb::Error::Other(Self::Error::Noise)
+                        icr.write(|w| w.ncf().set_bit());
+                        nb::Error::Other(Error::Noise)
                     } else if isr.ore().bit_is_set() {
-                        nb::Error::Other(Self::Error::Overrun)
+                        icr.write(|w| w.orecf().set_bit());
+                        nb::Error::Other(Error::Overrun)
                     } else if isr.rxne().bit_is_set() {
-                        // NOTE(read_volatile) see `write_volatile` below
-                        return Ok(unsafe { ptr::read_volatile(&(*$UART::ptr()).rdr() as *const _ as *const _) });
+                        // NOTE(unsafe) atomic read from stateless register
+                        return Ok(unsafe { (*$UART::ptr()).rdr().read().bits() as u16 & 0x1FF });
                     } else {
                         nb::Error::WouldBlock
                     })
                 }
             }
 
-            impl embedded_hal_nb::serial::Write<u8> for Serial<$UART> {
-                fn flush(&mut self) -> nb::Result<(), Self::Error> {
+            impl embedded_hal_nb::serial::Read<u8> for Rx<$UART> {
+                fn read(&mut self) -> nb::Result<u8, Self::Error> {
+                    self.read_word().map(|word| word as u8)
+                }
+            }
+
+            impl embedded_hal_nb::serial::Read<u16> for Rx<$UART> {
+                fn read(&mut self) -> nb::Result<u16, Self::Error> {
+                    self.read_word()
+                }
+            }
+
+            impl Tx<$UART> {
+                /// Waits for the end of the transmission
+                fn flush_word(&mut self) -> nb::Result<(), Error> {
                     // NOTE(unsafe) atomic read with no side effects
                     let isr = unsafe { (*$UART::ptr()).isr().read() };
 
@@ -105,14 +617,14 @@ macro_rules! uart {
                     }
                 }
 
-                fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+                /// Writes a 8 or 9 bit word
+                fn write_word(&mut self, word: u16) -> nb::Result<(), Error> {
                     // NOTE(unsafe) atomic read with no side effects
                     let isr = unsafe { (*$UART::ptr()).isr().read() };
 
                     if isr.txe().bit_is_set() {
                         // NOTE(unsafe) atomic write to stateless register
-                        // NOTE(write_volatile) 8-bit write that's not possible through the svd2rust API
-                        unsafe { (*$UART::ptr()).tdr().write(|w| w.tdr().bits(byte as u16)) }
+                        unsafe { (*$UART::ptr()).tdr().write(|w| w.tdr().bits(word & 0x1FF)) }
                         Ok(())
                     } else {
                         Err(nb::Error::WouldBlock)
@@ -120,6 +632,186 @@ macro_rules! uart {
                 }
             }
 
+            impl embedded_hal_nb::serial::Write<u8> for Tx<$UART> {
+                fn flush(&mut self) -> nb::Result<(), Self::Error> {
+                    self.flush_word()
+                }
+
+                fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+                    self.write_word(byte as u16)
+                }
+            }
+
+            impl embedded_hal_nb::serial::Write<u16> for Tx<$UART> {
+                fn flush(&mut self) -> nb::Result<(), Self::Error> {
+                    self.flush_word()
+                }
+
+                fn write(&mut self, word: u16) -> nb::Result<(), Self::Error> {
+                    self.write_word(word)
+                }
+            }
+
+            impl Rx<$UART> {
+                /// Returns true if a byte is waiting in the receive register without any error
+                /// flag that the next `read` would have to report
+                fn byte_ready(&self) -> bool {
+                    // NOTE(unsafe) atomic read with no side effects
+                    let isr = unsafe { (*$UART::ptr()).isr().read() };
+
+                    isr.rxne().bit_is_set()
+                        && isr.pe().bit_is_clear()
+                        && isr.fe().bit_is_clear()
+                        && isr.nf().bit_is_clear()
+                        && isr.ore().bit_is_clear()
+                }
+            }
+
+            impl embedded_io::ErrorType for Rx<$UART> {
+                type Error = Error;
+            }
+
+            impl embedded_io::ErrorType for Tx<$UART> {
+                type Error = Error;
+            }
+
+            impl embedded_io::ErrorType for Serial<$UART> {
+                type Error = Error;
+            }
+
+            impl embedded_io::Read for Rx<$UART> {
+                fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+
+                    buf[0] = nb::block!(Read::<u8>::read(self))?;
+
+                    // Return whatever else has already arrived, errors are left pending for the
+                    // next call so that no received byte is dropped
+                    let mut count = 1;
+                    while count < buf.len() && self.byte_ready() {
+                        buf[count] = nb::block!(Read::<u8>::read(self))?;
+                        count += 1;
+                    }
+
+                    Ok(count)
+                }
+            }
+
+            impl embedded_io::ReadReady for Rx<$UART> {
+                fn read_ready(&mut self) -> Result<bool, Self::Error> {
+                    // NOTE(unsafe) atomic read with no side effects
+                    Ok(unsafe { (*$UART::ptr()).isr().read().rxne().bit_is_set() })
+                }
+            }
+
+            impl embedded_io::Write for Tx<$UART> {
+                fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+
+                    nb::block!(Write::<u8>::write(self, buf[0]))?;
+
+                    let mut count = 1;
+                    while count < buf.len() {
+                        match Write::<u8>::write(self, buf[count]) {
+                            Ok(()) => count += 1,
+                            Err(nb::Error::WouldBlock) => break,
+                            Err(nb::Error::Other(e)) => return Err(e),
+                        }
+                    }
+
+                    Ok(count)
+                }
+
+                fn flush(&mut self) -> Result<(), Self::Error> {
+                    nb::block!(Write::<u8>::flush(self))
+                }
+            }
+
+            impl embedded_io::WriteReady for Tx<$UART> {
+                fn write_ready(&mut self) -> Result<bool, Self::Error> {
+                    // NOTE(unsafe) atomic read with no side effects
+                    Ok(unsafe { (*$UART::ptr()).isr().read().txe().bit_is_set() })
+                }
+            }
+
+            impl embedded_io::Read for Serial<$UART> {
+                fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+                    embedded_io::Read::read(&mut Rx::<$UART> { _uart: PhantomData }, buf)
+                }
+            }
+
+            impl embedded_io::ReadReady for Serial<$UART> {
+                fn read_ready(&mut self) -> Result<bool, Self::Error> {
+                    embedded_io::ReadReady::read_ready(&mut Rx::<$UART> { _uart: PhantomData })
+                }
+            }
+
+            impl embedded_io::Write for Serial<$UART> {
+                fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+                    embedded_io::Write::write(&mut Tx::<$UART> { _uart: PhantomData }, buf)
+                }
+
+                fn flush(&mut self) -> Result<(), Self::Error> {
+                    embedded_io::Write::flush(&mut Tx::<$UART> { _uart: PhantomData })
+                }
+            }
+
+            impl embedded_io::WriteReady for Serial<$UART> {
+                fn write_ready(&mut self) -> Result<bool, Self::Error> {
+                    embedded_io::WriteReady::write_ready(&mut Tx::<$UART> { _uart: PhantomData })
+                }
+            }
+
+            impl core::fmt::Write for Tx<$UART> {
+                fn write_str(&mut self, s: &str) -> core::fmt::Result {
+                    s.bytes()
+                        .try_for_each(|c| nb::block!(Write::<u8>::write(self, c)))
+                        .map_err(|_| core::fmt::Error)
+                }
+            }
+
+            impl core::fmt::Write for Serial<$UART> {
+                fn write_str(&mut self, s: &str) -> core::fmt::Result {
+                    core::fmt::Write::write_str(&mut Tx::<$UART> { _uart: PhantomData }, s)
+                }
+            }
+
+            impl embedded_hal_nb::serial::Read<u8> for Serial<$UART> {
+                fn read(&mut self) -> nb::Result<u8, Self::Error> {
+                    Rx::<$UART> { _uart: PhantomData }.read_word().map(|word| word as u8)
+                }
+            }
+
+            impl embedded_hal_nb::serial::Read<u16> for Serial<$UART> {
+                fn read(&mut self) -> nb::Result<u16, Self::Error> {
+                    Rx::<$UART> { _uart: PhantomData }.read_word()
+                }
+            }
+
+            impl embedded_hal_nb::serial::Write<u16> for Serial<$UART> {
+                fn flush(&mut self) -> nb::Result<(), Self::Error> {
+                    Tx::<$UART> { _uart: PhantomData }.flush_word()
+                }
+
+                fn write(&mut self, word: u16) -> nb::Result<(), Self::Error> {
+                    Tx::<$UART> { _uart: PhantomData }.write_word(word)
+                }
+            }
+
+            impl embedded_hal_nb::serial::Write<u8> for Serial<$UART> {
+                fn flush(&mut self) -> nb::Result<(), Self::Error> {
+                    Tx::<$UART> { _uart: PhantomData }.flush_word()
+                }
+
+                fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+                    Tx::<$UART> { _uart: PhantomData }.write_word(byte as u16)
+                }
+            }
+
         )+
     }
 }
@@ -127,6 +819,6 @@ macro_rules! uart {
 
 
 uart! {
-    UART1: (uart1, uart1en, apbenr2),
-    UART2: (uart2, uart2en, apbenr1),
+    UART1: (uart1, uart1en, apbenr2, rcc::uart1_kernel_clock),
+    UART2: (uart2, uart2en, apbenr1, Clocks::pclk),
 }
@@ -0,0 +1,188 @@
+//! Async serial I/O
+//!
+//! The `embedded_io_async` implementations park the task on the RXNE / TXE / TC interrupts.
+//! The matching `on_interrupt` function has to be called from the UART interrupt handler:
+//!
+//! ``` ignore
+//! #[interrupt]
+//! fn UART1() {
+//!     hal::serial::asynch::on_uart1_interrupt();
+//! }
+//! ```
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::task::{Poll, Waker};
+
+use cortex_m::interrupt::{self, Mutex};
+use embedded_hal_nb::serial::{Read, Write};
+
+use super::{Error, Rx, Serial, Tx};
+use crate::pac::{UART1, UART2};
+
+struct WakerSlot(Mutex<RefCell<Option<Waker>>>);
+
+impl WakerSlot {
+    const fn new() -> Self {
+        WakerSlot(Mutex::new(RefCell::new(None)))
+    }
+
+    fn register(&self, waker: &Waker) {
+        interrupt::free(|cs| {
+            let mut slot = self.0.borrow(cs).borrow_mut();
+            match slot.as_ref() {
+                Some(w) if w.will_wake(waker) => {}
+                _ => *slot = Some(waker.clone()),
+            }
+        })
+    }
+
+    fn wake(&self) {
+        if let Some(waker) = interrupt::free(|cs| self.0.borrow(cs).borrow_mut().take()) {
+            waker.wake();
+        }
+    }
+}
+
+macro_rules! uart_async {
+    ($($UART:ident: ($on_interrupt:ident, $RX_WAKER:ident, $TX_WAKER:ident),)+) => {
+        $(
+            static $RX_WAKER: WakerSlot = WakerSlot::new();
+            static $TX_WAKER: WakerSlot = WakerSlot::new();
+
+            /// Interrupt handler hook, wakes the tasks waiting on the UART
+            ///
+            /// The interrupt sources that fired are disabled again, the waiting futures
+            /// re-enable them when they are polled.
+            pub fn $on_interrupt() {
+                // NOTE(unsafe) read of the status register and read-modify-write of CR1 inside
+                // a critical section
+                let (rx, tx) = interrupt::free(|_| unsafe {
+                    let uart = &*$UART::ptr();
+                    let isr = uart.isr().read();
+                    let cr1 = uart.cr1().read();
+
+                    let rx = cr1.rxneie().bit_is_set()
+                        && (isr.rxne().bit_is_set() || isr.ore().bit_is_set());
+                    let tx = (cr1.txeie().bit_is_set() && isr.txe().bit_is_set())
+                        || (cr1.tcie().bit_is_set() && isr.tc().bit_is_set());
+
+                    uart.cr1().modify(|_, w| {
+                        if rx {
+                            w.rxneie().clear_bit();
+                        }
+                        if tx {
+                            w.txeie().clear_bit().tcie().clear_bit();
+                        }
+                        w
+                    });
+
+                    (rx, tx)
+                });
+
+                if rx {
+                    $RX_WAKER.wake();
+                }
+                if tx {
+                    $TX_WAKER.wake();
+                }
+            }
+
+            impl embedded_io_async::Read for Rx<$UART> {
+                async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+
+                    let first = poll_fn(|cx| match Read::<u8>::read(self) {
+                        Ok(byte) => Poll::Ready(Ok(byte)),
+                        Err(nb::Error::Other(e)) => Poll::Ready(Err(e)),
+                        Err(nb::Error::WouldBlock) => {
+                            $RX_WAKER.register(cx.waker());
+                            self.listen();
+                            Poll::Pending
+                        }
+                    })
+                    .await?;
+                    buf[0] = first;
+
+                    let mut count = 1;
+                    while count < buf.len() && self.byte_ready() {
+                        buf[count] = nb::block!(Read::<u8>::read(self))?;
+                        count += 1;
+                    }
+
+                    Ok(count)
+                }
+            }
+
+            impl embedded_io_async::Write for Tx<$UART> {
+                async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+
+                    poll_fn(|cx| match Write::<u8>::write(self, buf[0]) {
+                        Ok(()) => Poll::Ready(Ok(())),
+                        Err(nb::Error::Other(e)) => Poll::Ready(Err(e)),
+                        Err(nb::Error::WouldBlock) => {
+                            $TX_WAKER.register(cx.waker());
+                            self.listen();
+                            Poll::Pending
+                        }
+                    })
+                    .await?;
+
+                    let mut count = 1;
+                    while count < buf.len() {
+                        match Write::<u8>::write(self, buf[count]) {
+                            Ok(()) => count += 1,
+                            Err(nb::Error::WouldBlock) => break,
+                            Err(nb::Error::Other(e)) => return Err(e),
+                        }
+                    }
+
+                    Ok(count)
+                }
+
+                async fn flush(&mut self) -> Result<(), Self::Error> {
+                    poll_fn(|cx| match Write::<u8>::flush(self) {
+                        Ok(()) => Poll::Ready(Ok(())),
+                        Err(nb::Error::Other(e)) => Poll::Ready(Err(e)),
+                        Err(nb::Error::WouldBlock) => {
+                            $TX_WAKER.register(cx.waker());
+                            // NOTE(unsafe) read-modify-write of CR1 inside a critical section
+                            interrupt::free(|_| unsafe {
+                                (*$UART::ptr()).cr1().modify(|_, w| w.tcie().set_bit())
+                            });
+                            Poll::Pending
+                        }
+                    })
+                    .await
+                }
+            }
+
+            impl embedded_io_async::Read for Serial<$UART> {
+                async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+                    embedded_io_async::Read::read(&mut Rx::<$UART> { _uart: PhantomData }, buf).await
+                }
+            }
+
+            impl embedded_io_async::Write for Serial<$UART> {
+                async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+                    embedded_io_async::Write::write(&mut Tx::<$UART> { _uart: PhantomData }, buf).await
+                }
+
+                async fn flush(&mut self) -> Result<(), Error> {
+                    embedded_io_async::Write::flush(&mut Tx::<$UART> { _uart: PhantomData }).await
+                }
+            }
+        )+
+    }
+}
+
+uart_async! {
+    UART1: (on_uart1_interrupt, UART1_RX_WAKER, UART1_TX_WAKER),
+    UART2: (on_uart2_interrupt, UART2_RX_WAKER, UART2_TX_WAKER),
+}
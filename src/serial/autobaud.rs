@@ -0,0 +1,90 @@
+//! Automatic baud rate detection
+//!
+//! The UART measures the first received character and programs BRR by itself. Depending on
+//! the mode the host has to start with a character that has a known bit pattern.
+//!
+//! ``` ignore
+//! serial.start_auto_baud(AutoBaudMode::Frame0x7F);
+//! let detected = block!(serial.auto_baud_result(clocks))?;
+//! // The character used for the measurement is received as well
+//! let _ = block!(serial.read());
+//! ```
+
+use super::{Error, Serial};
+use crate::pac::{UART1, UART2};
+use crate::rcc::Clocks;
+use crate::time::Bps;
+
+/// Automatic baud rate detection mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AutoBaudMode {
+    /// Measures the start bit, the character must start with a 1 bit after the start bit
+    StartBit,
+    /// Measures from falling edge to falling edge, the character must start with `10xx`
+    FallingEdge,
+    /// Expects a 0x7F character
+    Frame0x7F,
+    /// Expects a 0x55 character
+    Frame0x55,
+}
+
+impl AutoBaudMode {
+    fn bits(self) -> u8 {
+        match self {
+            AutoBaudMode::StartBit => 0b00,
+            AutoBaudMode::FallingEdge => 0b01,
+            AutoBaudMode::Frame0x7F => 0b10,
+            AutoBaudMode::Frame0x55 => 0b11,
+        }
+    }
+}
+
+macro_rules! autobaud {
+    ($($UART:ident,)+) => {
+        $(
+            impl Serial<$UART> {
+                /// Starts an automatic baud rate detection on the next received character
+                pub fn start_auto_baud(&mut self, mode: AutoBaudMode) {
+                    self.with_disabled(|uart| {
+                        uart.cr2()
+                            .modify(|_, w| unsafe { w.abrmod().bits(mode.bits()).abren().set_bit() });
+                    });
+
+                    /* Re-arm the detection in case a previous one completed */
+                    self.uart.rqr().write(|w| w.abrrq().set_bit());
+                }
+
+                /// Returns the detected baud rate once the detection is complete
+                pub fn auto_baud_result(&mut self, clocks: Clocks) -> nb::Result<Bps, Error> {
+                    let isr = self.uart.isr().read();
+
+                    if isr.abre().bit_is_set() {
+                        self.stop_auto_baud();
+                        Err(nb::Error::Other(Error::AutoBaudRate))
+                    } else if isr.abrf().bit_is_set() {
+                        // The UART stays enabled, disabling it would drop the received character.
+                        // ABREN can stay set, the detection only runs again after an ABRRQ.
+                        Ok(self.baud_rate(clocks))
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                /// Stops the automatic baud rate detection, keeping the current BRR
+                ///
+                /// The UART is disabled for a moment, which clears the status flags and drops a
+                /// received character that has not been read yet.
+                pub fn stop_auto_baud(&mut self) {
+                    self.with_disabled(|uart| {
+                        uart.cr2().modify(|_, w| w.abren().clear_bit());
+                    });
+                }
+            }
+        )+
+    }
+}
+
+autobaud! {
+    UART1,
+    UART2,
+}
@@ -0,0 +1,355 @@
+//! Interrupt driven, ring buffered serial I/O
+//!
+//! This part has no DMA, so at high baud rates bytes have to be moved by the UART interrupt.
+//! `BufferedRx` and `BufferedTx` own a single-producer single-consumer ring. It is split into
+//! a handle for the interrupt handler and a handle for the main loop, each of them is unique,
+//! so the ring is accessed without critical sections.
+//!
+//! ``` ignore
+//! let (tx, rx) = serial.split();
+//! let rx: &'static mut BufferedRx<UART1, 64> = cortex_m::singleton!(: BufferedRx<UART1, 64> =
+//!     BufferedRx::new(rx)).unwrap();
+//! let (mut irq, mut reader) = rx.split();
+//!
+//! #[interrupt]
+//! fn UART1() {
+//!     // `irq` moved into the handler, e.g. through a `Mutex<RefCell<Option<_>>>`
+//!     irq.on_interrupt();
+//! }
+//! ```
+
+use core::cell::UnsafeCell;
+use core::convert::Infallible;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use embedded_hal_nb::serial::{Read, Write};
+
+use super::{Error, Rx, Tx};
+use crate::pac::{UART1, UART2};
+
+/// Lock-free single-producer single-consumer byte ring
+///
+/// `head` is only written by the producer and `tail` only by the consumer, so plain atomic
+/// loads and stores are enough (the Cortex-M0 has no compare-and-swap).
+struct Ring<const N: usize> {
+    buf: UnsafeCell<[u8; N]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+impl<const N: usize> Ring<N> {
+    const fn new() -> Self {
+        Ring {
+            buf: UnsafeCell::new([0; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.head
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.tail.load(Ordering::Acquire))
+    }
+
+    /// Producer side, returns `false` if the ring is full
+    fn push(&self, byte: u8) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        if head.wrapping_sub(self.tail.load(Ordering::Acquire)) >= N {
+            return false;
+        }
+        // NOTE(unsafe) the slot at `head` is not visible to the consumer until `head` moves
+        unsafe { (*self.buf.get())[head % N] = byte };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    /// Consumer side
+    fn pop(&self) -> Option<u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if self.head.load(Ordering::Acquire) == tail {
+            return None;
+        }
+        // NOTE(unsafe) the slot at `tail` is not reused by the producer until `tail` moves
+        let byte = unsafe { (*self.buf.get())[tail % N] };
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(byte)
+    }
+}
+
+/// Receive error statistics
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RxStats {
+    /// Bytes lost in the UART because the interrupt was serviced too late
+    pub hardware_overruns: u32,
+    /// Bytes dropped because the ring buffer was full
+    pub buffer_overruns: u32,
+    /// Bytes dropped because of a parity, framing or noise error
+    pub errors: u32,
+}
+
+/// Ring buffered serial receiver
+pub struct BufferedRx<UART, const N: usize> {
+    _rx: Rx<UART>,
+    ring: Ring<N>,
+    hardware_overruns: AtomicU32,
+    buffer_overruns: AtomicU32,
+    errors: AtomicU32,
+}
+
+/// Ring buffered serial transmitter
+pub struct BufferedTx<UART, const N: usize> {
+    _tx: Tx<UART>,
+    ring: Ring<N>,
+}
+
+/// Interrupt side of a `BufferedRx`, filling the ring
+pub struct RxInterrupt<'a, UART, const N: usize> {
+    rx: &'a BufferedRx<UART, N>,
+}
+
+/// Main loop side of a `BufferedRx`, draining the ring
+pub struct RxReader<'a, UART, const N: usize> {
+    rx: &'a BufferedRx<UART, N>,
+}
+
+/// Main loop side of a `BufferedTx`, filling the ring
+pub struct TxWriter<'a, UART, const N: usize> {
+    tx: &'a BufferedTx<UART, N>,
+}
+
+/// Interrupt side of a `BufferedTx`, draining the ring
+pub struct TxInterrupt<'a, UART, const N: usize> {
+    tx: &'a BufferedTx<UART, N>,
+}
+
+macro_rules! buffered {
+    ($($UART:ident,)+) => {
+        $(
+            // NOTE(unsafe) shared references only read the atomics, the ring is produced and
+            // the counters are written through the unique `RxInterrupt` and the ring is consumed
+            // through the unique `RxReader`
+            unsafe impl<const N: usize> Sync for BufferedRx<$UART, N> {}
+
+            // NOTE(unsafe) shared references only read the atomics, the ring is produced through
+            // the unique `TxWriter` and consumed through the unique `TxInterrupt`
+            unsafe impl<const N: usize> Sync for BufferedTx<$UART, N> {}
+
+            impl<const N: usize> BufferedRx<$UART, N> {
+                /// Takes the receiver and starts listening for the RXNE interrupt
+                pub fn new(mut rx: Rx<$UART>) -> Self {
+                    rx.listen();
+                    BufferedRx {
+                        _rx: rx,
+                        ring: Ring::new(),
+                        hardware_overruns: AtomicU32::new(0),
+                        buffer_overruns: AtomicU32::new(0),
+                        errors: AtomicU32::new(0),
+                    }
+                }
+
+                /// Splits into the interrupt handler side and the main loop side
+                pub fn split(&mut self) -> (RxInterrupt<'_, $UART, N>, RxReader<'_, $UART, N>) {
+                    let rx = &*self;
+                    (RxInterrupt { rx }, RxReader { rx })
+                }
+
+                /// Number of bytes waiting in the ring
+                pub fn len(&self) -> usize {
+                    self.ring.len()
+                }
+
+                /// Returns true if no byte is waiting in the ring
+                pub fn is_empty(&self) -> bool {
+                    self.ring.len() == 0
+                }
+
+                /// Returns the receive error statistics
+                pub fn stats(&self) -> RxStats {
+                    RxStats {
+                        hardware_overruns: self.hardware_overruns.load(Ordering::Relaxed),
+                        buffer_overruns: self.buffer_overruns.load(Ordering::Relaxed),
+                        errors: self.errors.load(Ordering::Relaxed),
+                    }
+                }
+
+                /// Stops listening for the RXNE interrupt and releases the receiver
+                pub fn free(self) -> Rx<$UART> {
+                    let mut rx = self._rx;
+                    rx.unlisten();
+                    rx
+                }
+            }
+
+            impl<const N: usize> RxInterrupt<'_, $UART, N> {
+                /// Moves received bytes into the ring, must be called from the UART interrupt
+                ///
+                /// Bytes received with a parity, framing or noise error are dropped and only
+                /// counted in the statistics.
+                pub fn on_interrupt(&mut self) {
+                    let mut rx = Rx::<$UART> { _uart: PhantomData };
+                    // The byte that came with an error is still in RDR after the flag is cleared
+                    let mut corrupt = false;
+                    loop {
+                        match Read::<u8>::read(&mut rx) {
+                            Ok(_) if corrupt => corrupt = false,
+                            Ok(byte) => {
+                                if !self.rx.ring.push(byte) {
+                                    increment(&self.rx.buffer_overruns);
+                                }
+                            }
+                            Err(nb::Error::Other(Error::Overrun)) => increment(&self.rx.hardware_overruns),
+                            // a byte can come with several errors, it is counted once
+                            Err(nb::Error::Other(_)) if corrupt => {}
+                            Err(nb::Error::Other(_)) => {
+                                increment(&self.rx.errors);
+                                corrupt = true;
+                            }
+                            Err(nb::Error::WouldBlock) => break,
+                        }
+                    }
+                }
+            }
+
+            impl<const N: usize> RxReader<'_, $UART, N> {
+                /// Reads a byte from the ring
+                pub fn read(&mut self) -> nb::Result<u8, Infallible> {
+                    self.rx.ring.pop().ok_or(nb::Error::WouldBlock)
+                }
+
+                /// Reads as many buffered bytes as fit into `buf`, returns the count
+                pub fn read_slice(&mut self, buf: &mut [u8]) -> usize {
+                    let mut count = 0;
+                    while count < buf.len() {
+                        match self.rx.ring.pop() {
+                            Some(byte) => buf[count] = byte,
+                            None => break,
+                        }
+                        count += 1;
+                    }
+                    count
+                }
+
+                /// Number of bytes waiting in the ring
+                pub fn len(&self) -> usize {
+                    self.rx.len()
+                }
+
+                /// Returns true if no byte is waiting in the ring
+                pub fn is_empty(&self) -> bool {
+                    self.rx.is_empty()
+                }
+
+                /// Returns the receive error statistics
+                pub fn stats(&self) -> RxStats {
+                    self.rx.stats()
+                }
+            }
+
+            impl<const N: usize> BufferedTx<$UART, N> {
+                /// Takes the transmitter, the TXE interrupt is enabled while data is pending
+                pub fn new(tx: Tx<$UART>) -> Self {
+                    BufferedTx {
+                        _tx: tx,
+                        ring: Ring::new(),
+                    }
+                }
+
+                /// Splits into the main loop side and the interrupt handler side
+                pub fn split(&mut self) -> (TxWriter<'_, $UART, N>, TxInterrupt<'_, $UART, N>) {
+                    let tx = &*self;
+                    (TxWriter { tx }, TxInterrupt { tx })
+                }
+
+                /// Number of bytes waiting for transmission
+                pub fn len(&self) -> usize {
+                    self.ring.len()
+                }
+
+                /// Returns true if nothing is waiting for transmission
+                pub fn is_empty(&self) -> bool {
+                    self.ring.len() == 0
+                }
+
+                /// Stops listening for the TXE interrupt and releases the transmitter
+                pub fn free(self) -> Tx<$UART> {
+                    let mut tx = self._tx;
+                    tx.unlisten();
+                    tx
+                }
+            }
+
+            impl<const N: usize> TxInterrupt<'_, $UART, N> {
+                /// Moves pending bytes into the UART, must be called from the UART interrupt
+                pub fn on_interrupt(&mut self) {
+                    let mut tx = Tx::<$UART> { _uart: PhantomData };
+                    // NOTE(unsafe) atomic read with no side effects
+                    while unsafe { (*$UART::ptr()).isr().read().txe().bit_is_set() } {
+                        match self.tx.ring.pop() {
+                            Some(byte) => {
+                                let _ = Write::<u8>::write(&mut tx, byte);
+                            }
+                            None => {
+                                tx.unlisten();
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+
+            impl<const N: usize> TxWriter<'_, $UART, N> {
+                /// Queues a byte for transmission
+                pub fn write(&mut self, byte: u8) -> nb::Result<(), Infallible> {
+                    if self.tx.ring.push(byte) {
+                        Tx::<$UART> { _uart: PhantomData }.listen();
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                /// Queues as many bytes of `buf` as fit into the ring, returns the count
+                pub fn write_slice(&mut self, buf: &[u8]) -> usize {
+                    let ring = &self.tx.ring;
+                    let count = buf.iter().take_while(|&&byte| ring.push(byte)).count();
+                    if count != 0 {
+                        Tx::<$UART> { _uart: PhantomData }.listen();
+                    }
+                    count
+                }
+
+                /// Waits until the ring is drained and the last frame has been sent
+                pub fn flush(&mut self) -> nb::Result<(), Infallible> {
+                    // NOTE(unsafe) atomic read with no side effects
+                    if self.tx.ring.len() == 0 && unsafe { (*$UART::ptr()).isr().read().tc().bit_is_set() } {
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                /// Number of bytes waiting for transmission
+                pub fn len(&self) -> usize {
+                    self.tx.len()
+                }
+
+                /// Returns true if nothing is waiting for transmission
+                pub fn is_empty(&self) -> bool {
+                    self.tx.is_empty()
+                }
+            }
+        )+
+    }
+}
+
+buffered! {
+    UART1,
+    UART2,
+}
+
+fn increment(counter: &AtomicU32) {
+    // Only the interrupt handler writes the counters, so load / store is enough
+    counter.store(counter.load(Ordering::Relaxed).wrapping_add(1), Ordering::Relaxed);
+}
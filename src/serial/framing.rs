@@ -0,0 +1,647 @@
+//! Packet framing over a serial port
+//!
+//! Frames are protected by a CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xFFFF),
+//! appended big endian to the payload, and delimited with either COBS (consistent overhead
+//! byte stuffing, frames terminated by 0x00) or SLIP (RFC 1055, frames delimited by 0xC0).
+//!
+//! The codecs only work on bytes and slices and don't touch the hardware, `Framed` connects
+//! them to anything implementing the `embedded_hal_nb::serial` traits, including `Serial`.
+//!
+//! ``` ignore
+//! let mut link: Framed<_, 64> = Framed::new(serial, Codec::Cobs);
+//! link.write_frame(&telemetry)?;
+//! if let Ok(command) = link.read_frame() {
+//!     // ...
+//! }
+//! ```
+
+use core::convert::Infallible;
+
+use embedded_hal_nb::serial::{Read, Write};
+
+/// Framing error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error<E> {
+    /// Error of the underlying serial port
+    Serial(E),
+    /// The frame does not fit into the buffer
+    Overflow,
+    /// The frame is not correctly encoded
+    Malformed,
+    /// The frame checksum does not match
+    Crc,
+}
+
+/// Framing codec
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    /// Consistent overhead byte stuffing
+    Cobs,
+    /// Serial line internet protocol
+    Slip,
+}
+
+/// Incremental CRC-16/CCITT-FALSE
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crc16(u16);
+
+impl Crc16 {
+    pub const fn new() -> Self {
+        Crc16(0xFFFF)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 ^= (byte as u16) << 8;
+            for _ in 0..8 {
+                self.0 = if self.0 & 0x8000 != 0 {
+                    (self.0 << 1) ^ 0x1021
+                } else {
+                    self.0 << 1
+                };
+            }
+        }
+    }
+
+    pub fn finish(self) -> u16 {
+        self.0
+    }
+}
+
+impl Default for Crc16 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Computes the CRC-16/CCITT-FALSE of `data`
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = Crc16::new();
+    crc.update(data);
+    crc.finish()
+}
+
+/// Consistent overhead byte stuffing
+pub mod cobs {
+    use super::{Codec, Decoder, Error};
+    use core::convert::Infallible;
+
+    /// Encodes `data`, passing every encoded byte including the 0x00 delimiter to `emit`
+    pub fn encode_with<I, E, F>(mut data: I, mut emit: F) -> Result<(), E>
+    where
+        I: Iterator<Item = u8> + Clone,
+        F: FnMut(u8) -> Result<(), E>,
+    {
+        loop {
+            // Length of the next run of non zero bytes, at most 254
+            let mut lookahead = data.clone();
+            let mut run = 0;
+            let mut zero = false;
+            while run < 254 {
+                match lookahead.next() {
+                    Some(0) => {
+                        zero = true;
+                        break;
+                    }
+                    Some(_) => run += 1,
+                    None => break,
+                }
+            }
+
+            emit(run as u8 + 1)?;
+            for byte in data.by_ref().take(run) {
+                emit(byte)?;
+            }
+
+            if zero {
+                data.next();
+            } else if run < 254 || data.clone().next().is_none() {
+                break;
+            }
+        }
+
+        emit(0)
+    }
+
+    /// Encodes `data` into `buf`, returns the encoded length including the delimiter
+    pub fn encode(data: &[u8], buf: &mut [u8]) -> Result<usize, Error<Infallible>> {
+        let mut len = 0;
+        encode_with(data.iter().copied(), |byte| {
+            *buf.get_mut(len).ok_or(Error::Overflow)? = byte;
+            len += 1;
+            Ok(())
+        })?;
+        Ok(len)
+    }
+
+    /// Decodes one frame from `data` into `buf`, returns the decoded length
+    ///
+    /// The trailing delimiter is optional.
+    pub fn decode(data: &[u8], buf: &mut [u8]) -> Result<usize, Error<Infallible>> {
+        let mut decoder = Decoder::new(Codec::Cobs);
+        decoder.decode(data, buf)
+    }
+}
+
+/// Serial line internet protocol
+pub mod slip {
+    use super::{Codec, Decoder, Error};
+    use core::convert::Infallible;
+
+    /// Frame delimiter
+    pub const END: u8 = 0xC0;
+    /// Escape byte
+    pub const ESC: u8 = 0xDB;
+    /// Escaped `END`
+    pub const ESC_END: u8 = 0xDC;
+    /// Escaped `ESC`
+    pub const ESC_ESC: u8 = 0xDD;
+
+    /// Encodes `data`, passing every encoded byte including the leading and trailing `END`
+    /// delimiters to `emit`
+    pub fn encode_with<I, E, F>(data: I, mut emit: F) -> Result<(), E>
+    where
+        I: Iterator<Item = u8>,
+        F: FnMut(u8) -> Result<(), E>,
+    {
+        // The leading delimiter flushes any line noise received before the frame
+        emit(END)?;
+        for byte in data {
+            match byte {
+                END => {
+                    emit(ESC)?;
+                    emit(ESC_END)?;
+                }
+                ESC => {
+                    emit(ESC)?;
+                    emit(ESC_ESC)?;
+                }
+                _ => emit(byte)?,
+            }
+        }
+        emit(END)
+    }
+
+    /// Encodes `data` into `buf`, returns the encoded length including the delimiters
+    pub fn encode(data: &[u8], buf: &mut [u8]) -> Result<usize, Error<Infallible>> {
+        let mut len = 0;
+        encode_with(data.iter().copied(), |byte| {
+            *buf.get_mut(len).ok_or(Error::Overflow)? = byte;
+            len += 1;
+            Ok(())
+        })?;
+        Ok(len)
+    }
+
+    /// Decodes one frame from `data` into `buf`, returns the decoded length
+    ///
+    /// The delimiters are optional.
+    pub fn decode(data: &[u8], buf: &mut [u8]) -> Result<usize, Error<Infallible>> {
+        let mut decoder = Decoder::new(Codec::Slip);
+        decoder.decode(data, buf)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    /// Waiting for the next COBS code byte, `zero` if the previous group implies a zero
+    CobsCode { zero: bool },
+    /// Inside a COBS group
+    CobsData { remaining: u8, zero: bool },
+    /// SLIP, `escaped` after an `ESC`
+    Slip { escaped: bool },
+    /// Skipping the rest of a broken frame up to the next delimiter
+    Discard,
+}
+
+/// Streaming frame decoder
+struct Decoder {
+    codec: Codec,
+    state: State,
+}
+
+impl Decoder {
+    fn new(codec: Codec) -> Self {
+        Decoder {
+            codec,
+            state: Self::initial(codec),
+        }
+    }
+
+    fn initial(codec: Codec) -> State {
+        match codec {
+            Codec::Cobs => State::CobsCode { zero: false },
+            Codec::Slip => State::Slip { escaped: false },
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state = Self::initial(self.codec);
+    }
+
+    /// Feeds one encoded byte, writing decoded bytes at `buf[*len]`
+    ///
+    /// Returns `Ok(true)` at the end of a non-empty frame, the decoder is ready for the next
+    /// frame afterwards.
+    fn feed(&mut self, byte: u8, buf: &mut [u8], len: &mut usize) -> Result<bool, Error<Infallible>> {
+        let delimiter = match self.codec {
+            Codec::Cobs => 0,
+            Codec::Slip => slip::END,
+        };
+
+        if byte == delimiter {
+            let state = self.state;
+            self.reset();
+            return match state {
+                State::Discard => {
+                    *len = 0;
+                    Ok(false)
+                }
+                State::CobsData { .. } | State::Slip { escaped: true } => {
+                    *len = 0;
+                    Err(Error::Malformed)
+                }
+                _ => Ok(*len != 0),
+            };
+        }
+
+        let mut push = |byte: u8, len: &mut usize| -> Result<(), Error<Infallible>> {
+            *buf.get_mut(*len).ok_or(Error::Overflow)? = byte;
+            *len += 1;
+            Ok(())
+        };
+
+        let result = match self.state {
+            State::Discard => Ok(()),
+            State::CobsCode { zero } => {
+                let pushed = if zero { push(0, len) } else { Ok(()) };
+                self.state = if byte == 1 {
+                    State::CobsCode { zero: true }
+                } else {
+                    State::CobsData {
+                        remaining: byte - 1,
+                        zero: byte != 0xFF,
+                    }
+                };
+                pushed
+            }
+            State::CobsData { remaining, zero } => {
+                self.state = if remaining == 1 {
+                    State::CobsCode { zero }
+                } else {
+                    State::CobsData {
+                        remaining: remaining - 1,
+                        zero,
+                    }
+                };
+                push(byte, len)
+            }
+            State::Slip { escaped: false } => {
+                if byte == slip::ESC {
+                    self.state = State::Slip { escaped: true };
+                    Ok(())
+                } else {
+                    push(byte, len)
+                }
+            }
+            State::Slip { escaped: true } => {
+                self.state = State::Slip { escaped: false };
+                match byte {
+                    slip::ESC_END => push(slip::END, len),
+                    slip::ESC_ESC => push(slip::ESC, len),
+                    _ => Err(Error::Malformed),
+                }
+            }
+        };
+
+        if result.is_err() {
+            self.state = State::Discard;
+            *len = 0;
+        }
+        result.map(|_| false)
+    }
+
+    /// Decodes a single frame from a slice, the delimiters are optional
+    fn decode(&mut self, data: &[u8], buf: &mut [u8]) -> Result<usize, Error<Infallible>> {
+        let mut len = 0;
+        for &byte in data {
+            if self.feed(byte, buf, &mut len)? {
+                return Ok(len);
+            }
+        }
+        // Missing trailing delimiter
+        let delimiter = match self.codec {
+            Codec::Cobs => 0,
+            Codec::Slip => slip::END,
+        };
+        self.feed(delimiter, buf, &mut len)?;
+        Ok(len)
+    }
+}
+
+/// Framed packet link over a serial port, receiving into a buffer of `N` bytes
+pub struct Framed<S, const N: usize> {
+    serial: S,
+    decoder: Decoder,
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<S, const N: usize> Framed<S, N> {
+    /// Wraps `serial` using `codec`
+    pub fn new(serial: S, codec: Codec) -> Self {
+        Framed {
+            serial,
+            decoder: Decoder::new(codec),
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    /// Releases the serial port
+    pub fn free(self) -> S {
+        self.serial
+    }
+}
+
+impl<S, const N: usize> Framed<S, N>
+where
+    S: Write<u8>,
+{
+    /// Sends `payload` followed by its CRC as one frame, blocking
+    pub fn write_frame(&mut self, payload: &[u8]) -> Result<(), Error<S::Error>> {
+        let crc = crc16(payload).to_be_bytes();
+        let data = payload.iter().copied().chain(crc.iter().copied());
+        let serial = &mut self.serial;
+        let emit = |byte| nb::block!(serial.write(byte)).map_err(Error::Serial);
+
+        match self.decoder.codec {
+            Codec::Cobs => cobs::encode_with(data, emit),
+            Codec::Slip => slip::encode_with(data, emit),
+        }
+    }
+}
+
+impl<S, const N: usize> Framed<S, N>
+where
+    S: Read<u8>,
+{
+    /// Receives the bytes available on the serial port, returns the payload of a complete
+    /// frame once its CRC has been checked
+    pub fn read_frame(&mut self) -> nb::Result<&[u8], Error<S::Error>> {
+        let len = loop {
+            let byte = self
+                .serial
+                .read()
+                .map_err(|e| e.map(Error::Serial))?;
+
+            match self.decoder.feed(byte, &mut self.buf, &mut self.len) {
+                Ok(true) => {
+                    let len = self.len;
+                    self.len = 0;
+                    break len;
+                }
+                Ok(false) => {}
+                Err(e) => return Err(nb::Error::Other(map_infallible(e))),
+            }
+        };
+
+        if len < 2 {
+            return Err(nb::Error::Other(Error::Malformed));
+        }
+        let (payload, crc) = self.buf[..len].split_at(len - 2);
+        if crc16(payload).to_be_bytes() != crc {
+            return Err(nb::Error::Other(Error::Crc));
+        }
+
+        Ok(payload)
+    }
+}
+
+fn map_infallible<E>(e: Error<Infallible>) -> Error<E> {
+    match e {
+        Error::Serial(e) => match e {},
+        Error::Overflow => Error::Overflow,
+        Error::Malformed => Error::Malformed,
+        Error::Crc => Error::Crc,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serial port looping written bytes back to the reader
+    struct Loopback {
+        buf: [u8; 600],
+        head: usize,
+        tail: usize,
+    }
+
+    impl Loopback {
+        fn new() -> Self {
+            Loopback {
+                buf: [0; 600],
+                head: 0,
+                tail: 0,
+            }
+        }
+    }
+
+    impl embedded_hal_nb::serial::ErrorType for Loopback {
+        type Error = embedded_hal_nb::serial::ErrorKind;
+    }
+
+    impl Read<u8> for Loopback {
+        fn read(&mut self) -> nb::Result<u8, Self::Error> {
+            if self.tail == self.head {
+                return Err(nb::Error::WouldBlock);
+            }
+            self.tail += 1;
+            Ok(self.buf[self.tail - 1])
+        }
+    }
+
+    impl Write<u8> for Loopback {
+        fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+            self.buf[self.head] = byte;
+            self.head += 1;
+            Ok(())
+        }
+
+        fn flush(&mut self) -> nb::Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert_eq!(crc16(b""), 0xFFFF);
+
+        let mut crc = Crc16::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0x29B1);
+    }
+
+    #[test]
+    fn cobs_vectors() {
+        let mut buf = [0; 16];
+        let mut check = |data: &[u8], encoded: &[u8]| {
+            let len = cobs::encode(data, &mut buf).unwrap();
+            assert_eq!(&buf[..len], encoded);
+            let mut decoded = [0; 16];
+            let len = cobs::decode(encoded, &mut decoded).unwrap();
+            assert_eq!(&decoded[..len], data);
+        };
+
+        check(&[0x00], &[0x01, 0x01, 0x00]);
+        check(&[0x00, 0x00], &[0x01, 0x01, 0x01, 0x00]);
+        check(
+            &[0x11, 0x22, 0x00, 0x33],
+            &[0x03, 0x11, 0x22, 0x02, 0x33, 0x00],
+        );
+        check(&[0x11, 0x00], &[0x02, 0x11, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn cobs_empty_payload() {
+        let mut buf = [0; 4];
+        assert_eq!(cobs::encode(&[], &mut buf), Ok(2));
+        assert_eq!(&buf[..2], &[0x01, 0x00]);
+
+        // an empty frame is skipped by the streaming decoder
+        let mut decoder = Decoder::new(Codec::Cobs);
+        let mut len = 0;
+        assert_eq!(decoder.feed(0x01, &mut buf, &mut len), Ok(false));
+        assert_eq!(decoder.feed(0x00, &mut buf, &mut len), Ok(false));
+    }
+
+    #[test]
+    fn cobs_group_boundary() {
+        let mut data = [0; 255];
+        for (i, byte) in data.iter_mut().enumerate().take(254) {
+            *byte = i as u8 + 1;
+        }
+        let mut buf = [0; 300];
+        let mut decoded = [0; 300];
+
+        // 254 non-zero bytes fill one group exactly
+        let len = cobs::encode(&data[..254], &mut buf).unwrap();
+        assert_eq!(len, 256);
+        assert_eq!(buf[0], 0xFF);
+        assert_eq!(&buf[1..255], &data[..254]);
+        assert_eq!(buf[255], 0x00);
+        let n = cobs::decode(&buf[..len], &mut decoded).unwrap();
+        assert_eq!(&decoded[..n], &data[..254]);
+
+        // a trailing zero after a full group needs two more groups
+        let len = cobs::encode(&data, &mut buf).unwrap();
+        assert_eq!(len, 258);
+        assert_eq!(&buf[255..258], &[0x01, 0x01, 0x00]);
+        let n = cobs::decode(&buf[..len], &mut decoded).unwrap();
+        assert_eq!(&decoded[..n], &data[..]);
+    }
+
+    #[test]
+    fn slip_escapes_end_and_esc() {
+        let data = [slip::END, slip::ESC, 0x01];
+        let encoded = [
+            slip::END,
+            slip::ESC,
+            slip::ESC_END,
+            slip::ESC,
+            slip::ESC_ESC,
+            0x01,
+            slip::END,
+        ];
+        let mut buf = [0; 16];
+        let len = slip::encode(&data, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &encoded);
+
+        let len = slip::decode(&encoded, &mut buf).unwrap();
+        assert_eq!(&buf[..len], &data);
+    }
+
+    #[test]
+    fn malformed_input() {
+        let mut buf = [0; 16];
+        // escape followed by an unknown byte
+        assert_eq!(
+            slip::decode(&[slip::ESC, 0x01], &mut buf),
+            Err(Error::Malformed)
+        );
+        // frame ending with an escape
+        assert_eq!(
+            slip::decode(&[0x01, slip::ESC, slip::END], &mut buf),
+            Err(Error::Malformed)
+        );
+        // group shorter than its code byte announces
+        assert_eq!(
+            cobs::decode(&[0x05, 0x11, 0x00], &mut buf),
+            Err(Error::Malformed)
+        );
+    }
+
+    #[test]
+    fn encode_overflow() {
+        let mut buf = [0; 3];
+        assert_eq!(cobs::encode(&[1, 2, 3], &mut buf), Err(Error::Overflow));
+        assert_eq!(slip::encode(&[1, 2], &mut buf), Err(Error::Overflow));
+    }
+
+    #[test]
+    fn decoder_recovers_after_overflow() {
+        let mut buf = [0; 4];
+        let mut len = 0;
+        let mut decoder = Decoder::new(Codec::Slip);
+
+        let mut result = Ok(false);
+        for &byte in &[slip::END, 1, 2, 3, 4, 5, 6] {
+            result = result.and(decoder.feed(byte, &mut buf, &mut len));
+        }
+        assert_eq!(result, Err(Error::Overflow));
+
+        // the rest of the broken frame is discarded up to the delimiter
+        assert_eq!(decoder.feed(7, &mut buf, &mut len), Ok(false));
+        assert_eq!(decoder.feed(slip::END, &mut buf, &mut len), Ok(false));
+
+        for &byte in &[0x0A, 0x0B] {
+            assert_eq!(decoder.feed(byte, &mut buf, &mut len), Ok(false));
+        }
+        assert_eq!(decoder.feed(slip::END, &mut buf, &mut len), Ok(true));
+        assert_eq!(&buf[..len], &[0x0A, 0x0B]);
+    }
+
+    #[test]
+    fn framed_round_trip() {
+        for codec in [Codec::Cobs, Codec::Slip] {
+            let mut link: Framed<_, 64> = Framed::new(Loopback::new(), codec);
+            let payload = [0x00, 0xC0, 0xDB, 0x12, 0x00];
+            link.write_frame(&payload).unwrap();
+            link.write_frame(&[0x34]).unwrap();
+
+            assert_eq!(link.read_frame(), Ok(&payload[..]));
+            assert_eq!(link.read_frame(), Ok(&[0x34][..]));
+            assert_eq!(link.read_frame(), Err(nb::Error::WouldBlock));
+        }
+    }
+
+    #[test]
+    fn framed_rejects_bad_crc() {
+        let mut serial = Loopback::new();
+        // "123456789" with its CRC, the last CRC byte corrupted
+        let mut frame = [0; 11];
+        frame[..9].copy_from_slice(b"123456789");
+        frame[9..].copy_from_slice(&[0x29, 0xB2]);
+        let mut encoded = [0; 16];
+        let len = cobs::encode(&frame, &mut encoded).unwrap();
+        for &byte in &encoded[..len] {
+            serial.write(byte).unwrap();
+        }
+
+        let mut link: Framed<_, 64> = Framed::new(serial, Codec::Cobs);
+        assert_eq!(link.read_frame(), Err(nb::Error::Other(Error::Crc)));
+    }
+}
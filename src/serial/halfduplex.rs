@@ -0,0 +1,156 @@
+//! Single-wire half-duplex mode
+//!
+//! Only the TX pin is used, it is switched to open drain and needs an external (or the
+//! internal) pull up. The receiver is disabled by the first write of a frame, so the own
+//! transmission is not echoed back, and enabled again by `read` or `flush` once the
+//! transmission complete (TC) flag is set.
+//!
+//! `HalfDuplex` keeps track of the line direction itself and can't be split, the interrupt
+//! driven and async receivers would never see a reply while the receiver is off.
+//!
+//! ``` ignore
+//! let (mut bus, tx) = Serial::half_duplex(p.UART1, tx, 1_000_000.bps(), rcc.clocks)?;
+//! for byte in command {
+//!     block!(bus.write(byte))?;
+//! }
+//! let status = block!(bus.read())?;
+//! ```
+
+use embedded_hal_nb::serial::{ErrorType, Read, Write};
+
+use super::{Config, Error, Serial};
+use crate::gpio::AlternateOpenDrain;
+use crate::pac::{UART1, UART2};
+use crate::rcc::Clocks;
+
+/// Serial port in single-wire half-duplex mode
+pub struct HalfDuplex<UART> {
+    serial: Serial<UART>,
+    transmitting: bool,
+}
+
+macro_rules! halfduplex {
+    ($($UART:ident: $uart:ident,)+) => {
+        $(
+            impl Serial<$UART> {
+                /// Configures the UART in single-wire half-duplex mode on the `tx` pin
+                pub fn half_duplex<C, TX>(
+                    uart: $UART,
+                    tx: TX,
+                    config: C,
+                    clocks: Clocks,
+                ) -> Result<(HalfDuplex<$UART>, TX), Error>
+                where
+                    C: Into<Config>,
+                    TX: AlternateOpenDrain,
+                {
+                    let tx = cortex_m::interrupt::free(|cs| tx.into_open_drain(cs));
+
+                    let mut bus = HalfDuplex {
+                        serial: Self::$uart(uart, config, clocks)?,
+                        transmitting: false,
+                    };
+                    bus.select();
+
+                    Ok((bus, tx))
+                }
+            }
+
+            impl HalfDuplex<$UART> {
+                /// Applies a new frame configuration, see `Serial::reconfigure`
+                pub fn reconfigure<C>(&mut self, config: C, clocks: Clocks) -> Result<(), Error>
+                where
+                    C: Into<Config>,
+                {
+                    self.serial.reconfigure(config, clocks)?;
+                    self.transmitting = false;
+                    self.select();
+                    Ok(())
+                }
+
+                /// Releases the UART peripheral
+                pub fn release(self) -> $UART {
+                    self.serial.release()
+                }
+
+                fn select(&mut self) {
+                    self.serial.with_disabled(|uart| {
+                        uart.cr3().modify(|_, w| w.hdsel().set_bit());
+                    });
+                }
+
+                /// Returns true while a transmission is in progress, re-enables the receiver
+                /// once it is complete
+                fn turn_around(&mut self) -> bool {
+                    if self.transmitting && self.serial.uart.isr().read().tc().bit_is_set() {
+                        self.serial.uart.cr1().modify(|_, w| w.re().set_bit());
+                        self.transmitting = false;
+                    }
+                    self.transmitting
+                }
+
+                fn read_word(&mut self) -> nb::Result<u16, Error> {
+                    if self.turn_around() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+                    Read::<u16>::read(&mut self.serial)
+                }
+
+                fn write_word(&mut self, word: u16) -> nb::Result<(), Error> {
+                    if !self.transmitting {
+                        self.serial.uart.cr1().modify(|_, w| w.re().clear_bit());
+                        self.transmitting = true;
+                    }
+                    Write::<u16>::write(&mut self.serial, word)
+                }
+
+                fn flush_word(&mut self) -> nb::Result<(), Error> {
+                    Write::<u16>::flush(&mut self.serial)?;
+                    self.turn_around();
+                    Ok(())
+                }
+            }
+
+            impl ErrorType for HalfDuplex<$UART> {
+                type Error = Error;
+            }
+
+            impl Read<u8> for HalfDuplex<$UART> {
+                fn read(&mut self) -> nb::Result<u8, Error> {
+                    self.read_word().map(|word| word as u8)
+                }
+            }
+
+            impl Read<u16> for HalfDuplex<$UART> {
+                fn read(&mut self) -> nb::Result<u16, Error> {
+                    self.read_word()
+                }
+            }
+
+            impl Write<u8> for HalfDuplex<$UART> {
+                fn write(&mut self, byte: u8) -> nb::Result<(), Error> {
+                    self.write_word(byte as u16)
+                }
+
+                fn flush(&mut self) -> nb::Result<(), Error> {
+                    self.flush_word()
+                }
+            }
+
+            impl Write<u16> for HalfDuplex<$UART> {
+                fn write(&mut self, word: u16) -> nb::Result<(), Error> {
+                    self.write_word(word)
+                }
+
+                fn flush(&mut self) -> nb::Result<(), Error> {
+                    self.flush_word()
+                }
+            }
+        )+
+    }
+}
+
+halfduplex! {
+    UART1: uart1,
+    UART2: uart2,
+}
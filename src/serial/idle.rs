@@ -0,0 +1,90 @@
+//! End of frame detection
+//!
+//! Variable length protocols delimit frames by a pause on the line. The UART reports it either
+//! as an idle line (one character time without a start bit) or, with the receiver timeout
+//! enabled, after a programmable number of bit times without a start bit.
+
+use embedded_hal_nb::serial::Read;
+
+use super::{Error, Serial};
+use crate::pac::{UART1, UART2};
+
+macro_rules! idle {
+    ($($UART:ident,)+) => {
+        $(
+            impl Serial<$UART> {
+                /// Enables the receiver timeout after `bits` bit times (up to 24 bits wide)
+                /// without a new start bit
+                pub fn enable_receiver_timeout(&mut self, bits: u32) {
+                    self.uart.rtor().modify(|_, w| unsafe { w.rto().bits(bits & 0x00FF_FFFF) });
+                    self.uart.cr2().modify(|_, w| w.rtoen().set_bit());
+                }
+
+                /// Disables the receiver timeout
+                pub fn disable_receiver_timeout(&mut self) {
+                    self.uart.cr2().modify(|_, w| w.rtoen().clear_bit());
+                }
+
+                /// Returns true if the receiver timeout has elapsed
+                pub fn is_receiver_timeout(&self) -> bool {
+                    self.uart.isr().read().rtof().bit_is_set()
+                }
+
+                /// Clears the receiver timeout flag
+                pub fn clear_receiver_timeout(&self) {
+                    self.uart.icr().write(|w| w.rtocf().set_bit());
+                }
+
+                /// Reads bytes into `buf` until the end of a frame, returns the frame length
+                ///
+                /// Blocks until the first byte arrives. The frame ends on an idle line, or on a
+                /// receiver timeout when it is enabled. Bytes that don't fit into `buf` are
+                /// received but dropped, the returned length never exceeds `buf.len()`.
+                pub fn read_until_idle(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+                    // With no byte waiting the end of frame flags are left over from an earlier
+                    // frame. A waiting byte may belong to a frame that has already ended, its
+                    // flags must be kept.
+                    if !self.is_rx_not_empty() {
+                        self.clear_idle_interrupt();
+                        self.clear_receiver_timeout();
+                    }
+                    let first = nb::block!(Read::<u8>::read(self))?;
+
+                    let mut count = 0;
+                    if let Some(slot) = buf.first_mut() {
+                        *slot = first;
+                        count = 1;
+                    }
+
+                    loop {
+                        match Read::<u8>::read(self) {
+                            Ok(byte) => {
+                                if let Some(slot) = buf.get_mut(count) {
+                                    *slot = byte;
+                                    count += 1;
+                                }
+                            }
+                            Err(nb::Error::Other(e)) => return Err(e),
+                            Err(nb::Error::WouldBlock) => {
+                                let rtoen = self.uart.cr2().read().rtoen().bit_is_set();
+                                if rtoen && self.is_receiver_timeout() {
+                                    self.clear_receiver_timeout();
+                                    return Ok(count);
+                                }
+                                if !rtoen && self.is_idle() {
+                                    self.clear_idle_interrupt();
+                                    return Ok(count);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        )+
+    }
+}
+
+idle! {
+    UART1,
+    UART2,
+}
@@ -0,0 +1,259 @@
+//! LIN (Local Interconnect Network) support
+//!
+//! The UART generates and detects the break field in LIN mode. The protected identifier and
+//! checksum helpers are plain functions that do not touch the hardware.
+
+use embedded_hal_nb::serial::Write;
+
+use super::{Error, Serial};
+use crate::pac::{UART1, UART2};
+
+/// Break detection length
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakLength {
+    /// 10 bit break detection
+    Bits10,
+    /// 11 bit break detection
+    Bits11,
+}
+
+/// LIN checksum model
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Checksum {
+    /// LIN 1.x, data bytes only
+    Classic,
+    /// LIN 2.x, protected identifier and data bytes
+    Enhanced,
+}
+
+/// Computes the protected identifier (identifier with parity bits) of a 6-bit frame identifier
+pub fn protected_id(id: u8) -> u8 {
+    let id = id & 0x3F;
+    let bit = |n: u8| (id >> n) & 1;
+    let p0 = bit(0) ^ bit(1) ^ bit(2) ^ bit(4);
+    let p1 = !(bit(1) ^ bit(3) ^ bit(4) ^ bit(5)) & 1;
+    id | (p0 << 6) | (p1 << 7)
+}
+
+/// Extracts the frame identifier from a protected identifier, `None` if the parity is wrong
+pub fn frame_id(pid: u8) -> Option<u8> {
+    let id = pid & 0x3F;
+    if protected_id(id) == pid {
+        Some(id)
+    } else {
+        None
+    }
+}
+
+/// Computes the checksum of a frame
+///
+/// The protected identifier is only included in the `Enhanced` model, the diagnostic frames
+/// 0x3C and 0x3D always use the `Classic` model.
+pub fn checksum(model: Checksum, pid: u8, data: &[u8]) -> u8 {
+    let model = match frame_id(pid) {
+        Some(0x3C) | Some(0x3D) => Checksum::Classic,
+        _ => model,
+    };
+
+    let mut sum: u16 = match model {
+        Checksum::Classic => 0,
+        Checksum::Enhanced => pid as u16,
+    };
+    for &byte in data {
+        sum += byte as u16;
+        if sum > 0xFF {
+            sum -= 0xFF;
+        }
+    }
+
+    !(sum as u8)
+}
+
+/// LIN frame with up to 8 data bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pid: u8,
+    data: [u8; 8],
+    len: u8,
+}
+
+impl Frame {
+    /// Creates a frame, `None` if `data` is longer than 8 bytes
+    pub fn new(id: u8, data: &[u8]) -> Option<Self> {
+        if data.len() > 8 {
+            return None;
+        }
+        let mut frame = Frame {
+            pid: protected_id(id),
+            data: [0; 8],
+            len: data.len() as u8,
+        };
+        frame.data[..data.len()].copy_from_slice(data);
+        Some(frame)
+    }
+
+    /// Frame identifier
+    pub fn id(&self) -> u8 {
+        self.pid & 0x3F
+    }
+
+    /// Protected identifier
+    pub fn pid(&self) -> u8 {
+        self.pid
+    }
+
+    /// Data bytes
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.len as usize]
+    }
+
+    /// Checksum of the frame
+    pub fn checksum(&self, model: Checksum) -> u8 {
+        checksum(model, self.pid, self.data())
+    }
+
+    /// Returns true if `checksum` matches the frame
+    pub fn verify(&self, model: Checksum, checksum: u8) -> bool {
+        self.checksum(model) == checksum
+    }
+}
+
+macro_rules! lin {
+    ($($UART:ident,)+) => {
+        $(
+            impl Serial<$UART> {
+                /// Enables LIN mode
+                ///
+                /// LIN frames are 8 data bits without parity and one stop bit, the frame format
+                /// and half-duplex mode are overridden accordingly. `reconfigure` leaves LIN mode,
+                /// this has to be called again afterwards.
+                pub fn enable_lin(&mut self, break_length: BreakLength) {
+                    self.with_disabled(|uart| {
+                        uart.cr1().modify(|_, w| w.m().clear_bit().pce().clear_bit());
+                        uart.cr3().modify(|_, w| w.hdsel().clear_bit());
+                        uart.cr2().modify(|_, w| unsafe {
+                            w.stop()
+                                .bits(0b00)
+                                .lbdl()
+                                .bit(break_length == BreakLength::Bits11)
+                                .linen()
+                                .set_bit()
+                        });
+                    });
+                }
+
+                /// Disables LIN mode
+                pub fn disable_lin(&mut self) {
+                    self.with_disabled(|uart| {
+                        uart.cr2().modify(|_, w| w.linen().clear_bit().lbdie().clear_bit());
+                    });
+                }
+
+                /// Requests a break frame to be sent after the current frame
+                pub fn send_break(&mut self) {
+                    self.uart.rqr().write(|w| w.sbkrq().set_bit());
+                }
+
+                /// Returns true if a break has been detected
+                pub fn is_break_detected(&self) -> bool {
+                    self.uart.isr().read().lbdf().bit_is_set()
+                }
+
+                /// Clears the break detected flag
+                pub fn clear_break_detected(&self) {
+                    self.uart.icr().write(|w| w.lbdcf().set_bit());
+                }
+
+                /// Sends a frame header: break, sync byte and protected identifier
+                pub fn write_lin_header(&mut self, id: u8) -> Result<(), Error> {
+                    nb::block!(Write::<u8>::flush(self))?;
+                    self.send_break();
+                    nb::block!(Write::<u8>::write(self, 0x55))?;
+                    nb::block!(Write::<u8>::write(self, protected_id(id)))
+                }
+
+                /// Sends a frame response: data bytes and checksum
+                pub fn write_lin_response(&mut self, frame: &Frame, model: Checksum) -> Result<(), Error> {
+                    for &byte in frame.data() {
+                        nb::block!(Write::<u8>::write(self, byte))?;
+                    }
+                    nb::block!(Write::<u8>::write(self, frame.checksum(model)))
+                }
+            }
+        )+
+    }
+}
+
+lin! {
+    UART1,
+    UART2,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protected_id_vectors() {
+        assert_eq!(protected_id(0x00), 0x80);
+        assert_eq!(protected_id(0x01), 0xC1);
+        assert_eq!(protected_id(0x10), 0x50);
+        assert_eq!(protected_id(0x3C), 0x3C);
+        assert_eq!(protected_id(0x3D), 0x7D);
+        assert_eq!(protected_id(0x3F), 0xBF);
+        // only the low 6 bits are the identifier
+        assert_eq!(protected_id(0xC1), 0xC1);
+    }
+
+    #[test]
+    fn frame_id_checks_parity() {
+        for id in 0..0x40 {
+            assert_eq!(frame_id(protected_id(id)), Some(id));
+        }
+        assert_eq!(frame_id(0x01), None);
+        assert_eq!(frame_id(0xC1 ^ 0x40), None);
+        assert_eq!(frame_id(0xC1 ^ 0x80), None);
+    }
+
+    #[test]
+    fn classic_checksum_wraps_carry() {
+        // example from the LIN specification: 0x4A + 0x55 + 0x93 + 0xE5 with carries = 0x19
+        let data = [0x4A, 0x55, 0x93, 0xE5];
+        assert_eq!(checksum(Checksum::Classic, protected_id(0x10), &data), 0xE6);
+        assert_eq!(checksum(Checksum::Classic, 0x80, &[0xFF, 0x01]), 0xFE);
+        assert_eq!(checksum(Checksum::Classic, 0x80, &[]), 0xFF);
+    }
+
+    #[test]
+    fn enhanced_checksum_includes_pid() {
+        let pid = protected_id(0x10);
+        // 0x50 + 0x4A + 0x55 + 0x93 + 0xE5 with carries = 0x69
+        let data = [0x4A, 0x55, 0x93, 0xE5];
+        assert_eq!(checksum(Checksum::Enhanced, pid, &data), 0x96);
+        assert_eq!(checksum(Checksum::Enhanced, 0xC1, &[0x3F]), !0x01);
+    }
+
+    #[test]
+    fn diagnostic_frames_use_classic_checksum() {
+        let data = [0x7F, 0x06, 0xB2, 0x00, 0xFF, 0x7F, 0xFF, 0xFF];
+        for id in [0x3C, 0x3D] {
+            let pid = protected_id(id);
+            assert_eq!(
+                checksum(Checksum::Enhanced, pid, &data),
+                checksum(Checksum::Classic, pid, &data)
+            );
+        }
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let frame = Frame::new(0x01, &[1, 2, 3]).unwrap();
+        assert_eq!(frame.id(), 0x01);
+        assert_eq!(frame.pid(), 0xC1);
+        assert_eq!(frame.data(), &[1, 2, 3]);
+        let sum = frame.checksum(Checksum::Enhanced);
+        assert!(frame.verify(Checksum::Enhanced, sum));
+        assert!(!frame.verify(Checksum::Classic, sum));
+        assert!(Frame::new(0x01, &[0; 9]).is_none());
+    }
+}
@@ -0,0 +1,93 @@
+//! `log` backend writing to a UART transmitter
+//!
+//! ``` ignore
+//! static LOGGER: SerialLogger<UART1> = SerialLogger::new();
+//!
+//! let (tx, rx) = serial.split();
+//! // NOTE(unsafe) called once, before any other `log` function is used
+//! unsafe { LOGGER.init(tx, LevelFilter::Info) };
+//! log::info!("booted");
+//! ```
+
+use core::cell::RefCell;
+use core::fmt::Write;
+
+use cortex_m::interrupt::{self, Mutex};
+use log::{LevelFilter, Log, Metadata, Record};
+
+use super::Tx;
+
+/// Logger formatting records into a UART
+///
+/// Records are written out blocking, with interrupts enabled. The transmitter is taken out of
+/// the logger while a record is written, a record logged from an interrupt handler in the
+/// meantime is dropped instead of being interleaved.
+pub struct SerialLogger<UART> {
+    tx: Mutex<RefCell<Option<Tx<UART>>>>,
+}
+
+impl<UART> SerialLogger<UART> {
+    /// Creates a logger without a transmitter, records are dropped until `init` is called
+    pub const fn new() -> Self {
+        SerialLogger {
+            tx: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    /// Takes the transmitter and installs the logger with the given level filter
+    ///
+    /// # Safety
+    ///
+    /// This uses `log::set_logger_racy` because the Cortex-M0 core has no compare-and-swap
+    /// instructions: it must not be called concurrently with any other `log` initialisation.
+    pub unsafe fn init(&'static self, tx: Tx<UART>, level: LevelFilter)
+    where
+        Tx<UART>: Write + Send,
+    {
+        interrupt::free(|cs| *self.tx.borrow(cs).borrow_mut() = Some(tx));
+        // Installing a logger only fails if one was installed before, which is fine to ignore
+        let _ = log::set_logger_racy(self);
+        self.set_level(level);
+    }
+
+    /// Changes the level filter
+    pub fn set_level(&self, level: LevelFilter) {
+        // NOTE(unsafe) a single word store, racing with a reader only delays the change
+        unsafe { log::set_max_level_racy(level) };
+    }
+
+    /// Uninstalls the transmitter, returning it
+    pub fn release(&self) -> Option<Tx<UART>> {
+        interrupt::free(|cs| self.tx.borrow(cs).borrow_mut().take())
+    }
+}
+
+impl<UART> Default for SerialLogger<UART> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<UART> Log for SerialLogger<UART>
+where
+    Tx<UART>: Write + Send,
+{
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        // Only the taking and putting back run in a critical section, the UART receivers and
+        // timers keep being served during the transmission
+        if let Some(mut tx) = interrupt::free(|cs| self.tx.borrow(cs).borrow_mut().take()) {
+            let _ = write!(tx, "[{}] {}\r\n", record.level(), record.args());
+            interrupt::free(|cs| *self.tx.borrow(cs).borrow_mut() = Some(tx));
+        }
+    }
+
+    fn flush(&self) {}
+}
@@ -0,0 +1,76 @@
+//! Multiprocessor communication (mute mode)
+//!
+//! On a multi-drop bus every node stays in mute mode and ignores the traffic until it is woken
+//! up, either by an idle line or by an address mark (MSB of the word set) carrying its own node
+//! address. Address marks are usually sent with 9-bit words through the `u16`
+//! `embedded_hal_nb::serial::Write` implementation.
+
+use super::Serial;
+use crate::pac::{UART1, UART2};
+
+/// Mute mode wake-up method
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WakeUp {
+    /// Wake up on an idle line
+    IdleLine,
+    /// Wake up on an address mark matching the node address
+    AddressMark,
+}
+
+/// Node address compared against the address marks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeAddress {
+    /// 4-bit address, compared against the 4 LSBs of the address mark
+    Bits4(u8),
+    /// 7-bit address, compared against the 7 LSBs of the address mark
+    Bits7(u8),
+}
+
+macro_rules! multiprocessor {
+    ($($UART:ident,)+) => {
+        $(
+            impl Serial<$UART> {
+                /// Enables mute mode with the given wake-up method and node address
+                ///
+                /// `reconfigure` leaves mute mode, this has to be called again afterwards.
+                pub fn enable_mute_mode(&mut self, wakeup: WakeUp, address: NodeAddress) {
+                    let (addm7, add) = match address {
+                        NodeAddress::Bits4(add) => (false, add & 0x0F),
+                        NodeAddress::Bits7(add) => (true, add & 0x7F),
+                    };
+
+                    self.with_disabled(|uart| {
+                        uart.cr2()
+                            .modify(|_, w| unsafe { w.add().bits(add).addm7().bit(addm7) });
+                        uart.cr1().modify(|_, w| {
+                            w.wake()
+                                .bit(wakeup == WakeUp::AddressMark)
+                                .mme()
+                                .set_bit()
+                        });
+                    });
+                }
+
+                /// Disables mute mode
+                pub fn disable_mute_mode(&mut self) {
+                    self.uart.cr1().modify(|_, w| w.mme().clear_bit());
+                }
+
+                /// Puts the receiver into mute mode until the next wake-up condition
+                pub fn enter_mute(&mut self) {
+                    self.uart.rqr().write(|w| w.mmrq().set_bit());
+                }
+
+                /// Returns true while the receiver is in mute mode
+                pub fn is_muted(&self) -> bool {
+                    self.uart.isr().read().rwu().bit_is_set()
+                }
+            }
+        )+
+    }
+}
+
+multiprocessor! {
+    UART1,
+    UART2,
+}
@@ -0,0 +1,163 @@
+//! RS-485 driver enable
+//!
+//! The UART can drive the transceiver driver enable (DE) itself on the RTS pin: it is asserted
+//! a configurable time before the start bit and released a configurable time after the last
+//! stop bit (DEM, DEAT and DEDT). The times are given in sample times, 1/16 bit or 1/8 bit
+//! with oversampling by 8.
+//!
+//! ``` ignore
+//! // with the RTS / DE pin switched to its UART alternate function
+//! serial.enable_driver_enable(DePolarity::ActiveHigh, 16, 16);
+//! ```
+//!
+//! If the transceiver is wired to another pin, `with_driver_enable` drives it in software: it
+//! is asserted before the first byte of a frame is written and released by `flush` once the
+//! transmission complete (TC) flag is set, i.e. after the last stop bit has left the shift
+//! register.
+//!
+//! ``` ignore
+//! let mut bus = serial.with_driver_enable(de)?;
+//! for byte in frame {
+//!     block!(bus.write(byte))?;
+//! }
+//! // Releases the bus
+//! block!(bus.flush())?;
+//! ```
+
+use core::fmt::Debug;
+
+use embedded_hal::digital::OutputPin;
+use embedded_hal_nb::serial::{self, ErrorKind, ErrorType, Read, Write};
+
+use super::Serial;
+use crate::pac::{UART1, UART2};
+
+/// Driver enable polarity
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DePolarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+macro_rules! rs485 {
+    ($($UART:ident,)+) => {
+        $(
+            impl Serial<$UART> {
+                /// Lets the UART drive the DE output, asserted `assertion` sample times before
+                /// the start bit and released `deassertion` sample times after the stop bit
+                ///
+                /// Both times are limited to 31 sample times. `reconfigure` turns the driver
+                /// enable off again.
+                pub fn enable_driver_enable(&mut self, polarity: DePolarity, assertion: u8, deassertion: u8) {
+                    self.with_disabled(|uart| {
+                        uart.cr3().modify(|_, w| {
+                            w.dep()
+                                .bit(polarity == DePolarity::ActiveLow)
+                                .dem()
+                                .set_bit()
+                        });
+                        uart.cr1().modify(|_, w| unsafe {
+                            w.deat()
+                                .bits(assertion.min(31))
+                                .dedt()
+                                .bits(deassertion.min(31))
+                        });
+                    });
+                }
+
+                /// Stops driving the DE output
+                pub fn disable_driver_enable(&mut self) {
+                    self.with_disabled(|uart| {
+                        uart.cr3().modify(|_, w| w.dem().clear_bit());
+                    });
+                }
+            }
+        )+
+    }
+}
+
+rs485! {
+    UART1,
+    UART2,
+}
+
+/// Error of a serial port with a software driver enable
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error<E, P> {
+    /// Error of the serial port
+    Serial(E),
+    /// Error of the driver enable pin
+    DriverEnable(P),
+}
+
+impl<E, P> serial::Error for Error<E, P>
+where
+    E: serial::Error,
+    P: Debug,
+{
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Serial(e) => e.kind(),
+            Error::DriverEnable(_) => ErrorKind::Other,
+        }
+    }
+}
+
+/// Serial port driving an RS-485 transceiver
+pub struct Rs485<UART, DE> {
+    serial: Serial<UART>,
+    de: DE,
+}
+
+impl<UART> Serial<UART> {
+    /// Drives `de` high around transmissions
+    pub fn with_driver_enable<DE>(self, mut de: DE) -> Result<Rs485<UART, DE>, DE::Error>
+    where
+        DE: OutputPin,
+    {
+        de.set_low()?;
+        Ok(Rs485 { serial: self, de })
+    }
+}
+
+impl<UART, DE> Rs485<UART, DE> {
+    /// Releases the serial port and the driver enable pin
+    pub fn release(self) -> (Serial<UART>, DE) {
+        (self.serial, self.de)
+    }
+}
+
+impl<UART, DE> ErrorType for Rs485<UART, DE>
+where
+    Serial<UART>: ErrorType,
+    DE: OutputPin,
+{
+    type Error = Error<<Serial<UART> as ErrorType>::Error, DE::Error>;
+}
+
+impl<UART, DE> Read<u8> for Rs485<UART, DE>
+where
+    Serial<UART>: Read<u8>,
+    DE: OutputPin,
+{
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        Read::<u8>::read(&mut self.serial).map_err(|e| e.map(Error::Serial))
+    }
+}
+
+impl<UART, DE> Write<u8> for Rs485<UART, DE>
+where
+    Serial<UART>: Write<u8>,
+    DE: OutputPin,
+{
+    fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        self.de.set_high().map_err(Error::DriverEnable)?;
+        Write::<u8>::write(&mut self.serial, byte).map_err(|e| e.map(Error::Serial))
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Write::<u8>::flush(&mut self.serial).map_err(|e| e.map(Error::Serial))?;
+        self.de.set_low().map_err(Error::DriverEnable)?;
+        Ok(())
+    }
+}
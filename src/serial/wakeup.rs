@@ -0,0 +1,71 @@
+//! Wake-up from stop mode on UART activity
+//!
+//! The UART keeps listening in stop mode and raises the wake-up flag (WUF) on the selected
+//! event. This requires the UART kernel clock to keep running in stop mode, which is only
+//! possible on UART1 clocked from HSI or LSE (see `Rcc::set_uart1_clock`), and the UART
+//! interrupt to be enabled (`listen(Event::WakeUp)` and NVIC) to actually wake the core up.
+//! UART2 is always clocked from PCLK and can't wake the core up.
+
+use super::{Error, Serial};
+use crate::pac::UART1;
+use crate::rcc::{self, UartClock};
+
+/// Event that wakes the core up from stop mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WakeUpSource {
+    /// Address match, see `multiprocessor::NodeAddress`
+    AddressMatch,
+    /// Start bit detection
+    StartBit,
+    /// Reception of a complete word
+    RxNotEmpty,
+}
+
+impl WakeUpSource {
+    fn bits(self) -> u8 {
+        match self {
+            WakeUpSource::AddressMatch => 0b00,
+            WakeUpSource::StartBit => 0b10,
+            WakeUpSource::RxNotEmpty => 0b11,
+        }
+    }
+}
+
+impl Serial<UART1> {
+    /// Enables the UART in stop mode, waking the core up on `source`
+    ///
+    /// Returns `Error::WakeUpClock` if the UART is not clocked from HSI or LSE.
+    pub fn enable_wakeup(&mut self, source: WakeUpSource) -> Result<(), Error> {
+        if !matches!(rcc::uart1_clock(), UartClock::Hsi | UartClock::Lse) {
+            return Err(Error::WakeUpClock);
+        }
+
+        self.with_disabled(|uart| {
+            uart.cr3().modify(|_, w| unsafe { w.wus().bits(source.bits()) });
+            uart.cr1().modify(|_, w| w.uesm().set_bit());
+        });
+        Ok(())
+    }
+
+    /// Disables the UART in stop mode
+    pub fn disable_wakeup(&mut self) {
+        self.uart.cr1().modify(|_, w| w.uesm().clear_bit());
+    }
+
+    /// Returns true if the UART may be stopped without losing a frame: the receiver
+    /// is enabled and no reception is in progress
+    pub fn ready_for_stop(&self) -> bool {
+        let isr = self.uart.isr().read();
+        isr.reack().bit_is_set() && isr.busy().bit_is_clear()
+    }
+
+    /// Returns true if the wake-up flag is set
+    pub fn is_wakeup(&self) -> bool {
+        self.uart.isr().read().wuf().bit_is_set()
+    }
+
+    /// Clears the wake-up flag
+    pub fn clear_wakeup(&self) {
+        self.uart.icr().write(|w| w.wucf().set_bit());
+    }
+}
@@ -114,7 +114,7 @@ macro_rules! timers {
                     match event {
                         Event::TimeOut => {
                             // Enable update event interrupt
-                            self.tim.dier().write(|w| w.uie().set_bit());
+                            self.tim.dier().modify(|_, w| w.uie().set_bit());
                         }
                     }
                 }
@@ -123,8 +123,8 @@ macro_rules! timers {
                 pub fn unlisten(&mut self, event: Event) {
                     match event {
                         Event::TimeOut => {
-                            // Enable update event interrupt
-                            self.tim.dier().write(|w| w.uie().clear_bit());
+                            // Disable update event interrupt
+                            self.tim.dier().modify(|_, w| w.uie().clear_bit());
                         }
                     }
                 }